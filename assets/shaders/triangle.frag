@@ -2,10 +2,37 @@
 
 in vec3 vColor;
 in vec3 vPos;
+in vec3 vNormal;
+
+// Point light and eye, in world space
+uniform vec3 lightSource;
+uniform vec3 viewer;
 
 out vec4 Color;
 
+const float ambient = 0.3;
+const float diffuse = 0.9;
+const float specular = 0.25;
+const float shininess = 24.0;
+const float falloff = 0.03;    // Of the light, with the square of the distance
+
+// Lit per fragment: walls are merged into quads many cells long, too
+// coarse to light at the vertices
 void main()
 {
-    Color = vec4(vColor, 1.0f);
+    if (dot(vNormal, vNormal) == 0.0) {
+        Color = vec4(vColor * ambient, 1.0f);
+        return;
+    }
+    vec3 n = normalize(vNormal);
+    vec3 toLight = lightSource - vPos;
+    float dist = length(toLight);
+    vec3 l = toLight / max(dist, 0.0001);
+    vec3 h = normalize(l + normalize(viewer - vPos));
+    float strength = 1.0 / (1.0 + falloff * dist * dist);
+    float lambert = max(dot(n, l), 0.0);
+    float highlight = lambert > 0.0 ? pow(max(dot(n, h), 0.0), shininess) : 0.0;
+    vec3 lit = vColor * (ambient + diffuse * lambert * strength)
+        + vec3(specular * highlight * strength);
+    Color = vec4(lit, 1.0f);
 }
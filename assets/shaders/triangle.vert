@@ -2,14 +2,19 @@
 
 layout (location = 0) in vec3 Position;
 layout (location = 1) in vec3 Color;
+layout (location = 2) in vec3 Normal;
 
+uniform mat4 Model;
 uniform mat4 MVP;
 
 out vec3 vColor;
 out vec3 vPos;
+out vec3 vNormal;
 void main()
 {
     gl_Position = MVP * vec4(Position, 1.0);
-    vPos = Position;
+    // World space, Model only moves and turns
+    vPos = (Model * vec4(Position, 1.0)).xyz;
+    vNormal = mat3(Model) * Normal;
     vColor = Color;
 }
@@ -0,0 +1,93 @@
+// Maze generation benchmarks.
+//
+// Run with `cargo bench`. The game is a binary crate, so the maze
+// modules are compiled into the benchmark directly. Each case prints
+// the fastest of a few runs.
+
+#![allow(dead_code)]
+
+extern crate rand;
+extern crate rand_chacha;
+
+#[path = "../src/mazegen.rs"]
+mod mazegen;
+#[path = "../src/mazealgo.rs"]
+mod mazealgo;
+#[path = "../src/mazesolve.rs"]
+mod mazesolve;
+#[path = "../src/mazefile.rs"]
+mod mazefile;
+#[path = "../src/mazemask.rs"]
+mod mazemask;
+#[path = "../src/mazerooms.rs"]
+mod mazerooms;
+
+use std::time::Duration;
+use std::time::Instant;
+use crate::mazegen::*;
+
+// Fastest time of `f` over `runs` runs.
+fn fastest<F: FnMut()>(
+    runs: usize,
+    mut f: F
+) -> Duration {
+
+    let mut best: Option<Duration> = None;
+    for _ in 0..runs {
+        let start = Instant::now();
+        f();
+        let time = start.elapsed();
+        best = Some(best.map_or(time, |b| b.min(time)));
+    }
+    return best.unwrap();
+
+}
+
+fn report(
+    name: &str,
+    size: usize,
+    time: Duration
+) {
+    println!(
+        "{:<24} {:>5}x{:<5} {:>10.1} ms {:>8.1} ns/cell",
+        name, size, size,
+        time.as_secs_f64() * 1e3,
+        time.as_secs_f64() * 1e9 / (size * size) as f64
+    );
+}
+
+fn main() {
+
+    println!(
+        "Maze2: {} byte(s) per cell",
+        std::mem::size_of::<MazeCell2>()
+    );
+
+    let sizes: [(usize, usize); 3] = [(256, 10), (1024, 3), (4096, 3)];
+    let generators = ["prim", "backtracker", "binary-tree", "sidewinder", "kruskal", "eller"];
+    for &(size, runs) in sizes.iter() {
+        for name in generators.iter() {
+            let generator = mazealgo::generator_by_name(name).unwrap();
+            let time = fastest(runs, || {
+                let mut maze = Maze2::empty(size, size);
+                maze.generate_using(&generator, 1);
+            });
+            report(name, size, time);
+        }
+    }
+
+    // Queries on a finished maze
+    for &(size, runs) in sizes.iter() {
+        let mut maze = Maze2::empty(size, size);
+        maze.generate_with_seed(1);
+        let time = fastest(runs, || {
+            maze.distance_map((0, 0));
+        });
+        report("distance map", size, time);
+        let time = fastest(runs, || {
+            maze.to_bytes();
+        });
+        report("binary encoding", size, time);
+    }
+
+}
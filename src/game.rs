@@ -1,13 +1,22 @@
 extern crate glfw;
 
+use crate::mazegen::Direction;
 use crate::mazegen::Maze2;
+use crate::maze3::Maze3;
+use crate::mazelocks::Locks;
+use crate::mazemesh::BRIDGE_HEIGHT;
 use crate::ctr;
 
+// Distance kept between the player and the middle of a grid maze wall.
+const WALL_CLEARANCE: f32 = 0.2;
+
 pub struct Player {
     pos: (f32, f32, f32),
     vel: (f32, f32, f32),
     look_dir: (f32, f32),
-    walk_speed: f32
+    walk_speed: f32,
+    layer: u8,      // Axis of the passage taken through a crossing
+    keys: Vec<usize>    // Colours of the keys picked up
 }
 
 impl Player {
@@ -20,17 +29,48 @@ impl Player {
             pos: pos,
             vel: (0.0, 0.0, 0.0),
             look_dir: (0.0, 0.0),
-            walk_speed: 1.5
+            walk_speed: 1.5,
+            layer: 0,
+            keys: Vec::new()
         }
 
     }
 
+    // Put the player back at a position, standing still and without
+    // keys, e.g. in a new maze.
+    pub fn respawn(
+        &mut self,
+        pos: (f32, f32, f32)
+    ) {
+
+        self.pos = pos;
+        self.vel = (0.0, 0.0, 0.0);
+        self.layer = 0;
+        self.keys.clear();
+
+    }
+
     pub fn get_pos(
         &self
     ) -> (f32, f32, f32) {
         return self.pos;
     }
 
+    // Lamp the player carries, held a little above the eyes so that it
+    // lights the floor ahead too.
+    pub fn get_light_pos(
+        &self
+    ) -> (f32, f32, f32) {
+        return (self.pos.0, self.pos.1, self.pos.2 + 0.2);
+    }
+
+    pub fn has_key(
+        &self,
+        colour: usize
+    ) -> bool {
+        return self.keys.contains(&colour);
+    }
+
     pub fn get_look_vec(
         &self    
     ) -> (f32, f32, f32) {
@@ -69,9 +109,160 @@ impl Player {
 
     }
 
+    // Move through a maze. Doors stay closed until the player walks up
+    // to the key of their colour.
     pub fn update_in_maze(
         &mut self,
         maze: &Maze2,
+        locks: &Locks,
+        dt: f32
+    ) {
+
+        // On a crossing only the passage the player came by leads on
+        let cell_x: usize = self.pos.0 as usize;
+        let cell_y: usize = self.pos.1 as usize;
+        let crossing = maze.get_cell((cell_x, cell_y)).and_then(|c| c.get_crossing());
+        let layer = self.layer;
+        let locked: Vec<Direction> = Direction::ALL.iter().copied()
+            .filter(|&dir| {
+                return locks.door_at(maze, cell_x, cell_y, dir)
+                    .map_or(false, |c| !self.has_key(c));
+            })
+            .collect();
+        self.move_in_cell(|dir| {
+            maze.is_open((cell_x, cell_y), dir).unwrap_or(false) && !locked.contains(&dir)
+                && (crossing.is_none() || dir.axis() == layer)
+        }, dt);
+
+        // Teleport across the seam of wrapping mazes
+        let size = maze.get_size();
+        let wrap = maze.get_wrap();
+        if wrap.0 {
+            self.pos.0 = self.pos.0.rem_euclid(size.0 as f32);
+        }
+        if wrap.1 {
+            self.pos.1 = self.pos.1.rem_euclid(size.1 as f32);
+        }
+
+        // Entering a crossing picks the passage along the way the player
+        // moved. Over the middle of the cell the player rises onto the
+        // bridge or ducks into the tunnel.
+        let (new_x, new_y) = (self.pos.0 as usize, self.pos.1 as usize);
+        match maze.get_cell((new_x, new_y)).and_then(|c| c.get_crossing()) {
+            Some(over) => {
+                if (new_x, new_y) != (cell_x, cell_y) {
+                    self.layer = if new_x != cell_x { 0 } else { 1 };
+                }
+                let along = if self.layer == 0 { self.pos.0.fract() } else { self.pos.1.fract() };
+                let rise = ((1.0 - 2.0 * (along - 0.5).abs()) * 2.0).min(1.0);
+                let height = if over == self.layer {
+                    BRIDGE_HEIGHT + 0.25
+                } else {
+                    BRIDGE_HEIGHT - 0.25
+                };
+                self.pos.2 = 0.5 + (height - 0.5) * rise;
+            }
+            None => {
+                self.pos.2 = 0.5;
+            }
+        }
+
+        for (colour, &(x, y)) in locks.keys.iter().enumerate() {
+            let dist = (self.pos.0 - x as f32 - 0.5).powi(2) + (self.pos.1 - y as f32 - 0.5).powi(2);
+            if dist < 0.3 * 0.3 && !self.has_key(colour) {
+                self.keys.push(colour);
+            }
+        }
+
+    }
+
+    // Move through a multi-floor maze. On a stairwell ramp the player
+    // rises with x, from the lower floor at the -x side of the cell to
+    // the upper floor at the +x side.
+    pub fn update_in_maze3(
+        &mut self,
+        maze: &Maze3,
+        dt: f32
+    ) {
+
+        let size = maze.get_size();
+        let cell_x: usize = self.pos.0 as usize;
+        let cell_y: usize = self.pos.1 as usize;
+        let floor: usize = ((self.pos.2 - 0.5).max(0.0) as usize).min(size.2 - 1);
+
+        // Floor at the bottom of the ramp, if on one
+        let mut ramp: Option<usize> = None;
+        if maze.is_cell_open(cell_x, cell_y, floor, 4) {
+            ramp = Some(floor);
+        } else if maze.is_cell_open(cell_x, cell_y, floor, 5) {
+            ramp = Some(floor - 1);
+        }
+
+        match ramp {
+            Some(base) => {
+                // Exits of the floor at the end of the ramp the player
+                // is at: the lower one towards -x, the upper one towards +x
+                let end = if self.pos.0.fract() < 0.5 { base } else { base + 1 };
+                self.move_in_cell(|dir| maze.is_cell_open(cell_x, cell_y, end, dir.as_u8()), dt);
+                let new_x = self.pos.0 as usize;
+                self.pos.2 = base as f32 + 0.5 + if new_x < cell_x {
+                    0.0
+                } else if new_x > cell_x {
+                    1.0
+                } else {
+                    self.pos.0.fract()
+                };
+            }
+            None => {
+                self.move_in_cell(|dir| maze.is_cell_open(cell_x, cell_y, floor, dir.as_u8()), dt);
+                self.pos.2 = floor as f32 + 0.5;
+            }
+        }
+
+    }
+
+    // Move through a maze on any grid, given its walls as polylines.
+    // The player slides along walls, kept `WALL_CLEARANCE` away from
+    // them.
+    pub fn update_in_walls(
+        &mut self,
+        walls: &Vec<Vec<(f32, f32)>>,
+        dt: f32
+    ) {
+
+        self.pos.0 += self.vel.0 * dt;
+        self.pos.1 += self.vel.1 * dt;
+        for wall in walls.iter() {
+            for segment in wall.windows(2) {
+                let (a, b) = (segment[0], segment[1]);
+                let along = (b.0 - a.0, b.1 - a.1);
+                let length_sq = along.0 * along.0 + along.1 * along.1;
+                if length_sq == 0.0 {
+                    continue;
+                }
+
+                // Push the player straight away from the nearest point
+                // of the segment
+                let t = ((self.pos.0 - a.0) * along.0 + (self.pos.1 - a.1) * along.1) / length_sq;
+                let t = t.max(0.0).min(1.0);
+                let nearest = (a.0 + along.0 * t, a.1 + along.1 * t);
+                let away = (self.pos.0 - nearest.0, self.pos.1 - nearest.1);
+                let dist = (away.0 * away.0 + away.1 * away.1).sqrt();
+                if dist > 0.0 && dist < WALL_CLEARANCE {
+                    self.pos.0 = nearest.0 + away.0 / dist * WALL_CLEARANCE;
+                    self.pos.1 = nearest.1 + away.1 / dist * WALL_CLEARANCE;
+                }
+            }
+        }
+        self.pos.2 = 0.5;
+
+    }
+
+    // Move by the current velocity, stopping at walls of the current
+    // cell. `is_open` tells whether the cell is open in a direction.
+    fn move_in_cell<F: Fn(Direction) -> bool>(
+        &mut self,
+        is_open: F,
         dt: f32
     ) {
 
@@ -80,13 +271,11 @@ impl Player {
         let mut displacement_y: f32 = self.vel.1 * dt;
 
         // Check colission 
-        let cell_x: usize = self.pos.0 as usize;
-        let cell_y: usize = self.pos.1 as usize;
         let rel_x: f32 = self.pos.0.fract();
         let rel_y: f32 = self.pos.1.fract();
         
         if rel_x + displacement_x > 0.75 && self.vel.0 > 0.0 && (
-            !maze.is_cell_open(cell_x, cell_y, 0) || 
+            !is_open(Direction::PosX) || 
             rel_y < 0.25 || 
             rel_y > 0.75
         ) {
@@ -94,7 +283,7 @@ impl Player {
             displacement_x = 0.0;
         }
         if rel_x + displacement_x < 0.25 && self.vel.0 < 0.0 && (
-            !maze.is_cell_open(cell_x, cell_y, 1) || 
+            !is_open(Direction::NegX) || 
             rel_y < 0.25 || 
             rel_y > 0.75
         ) {
@@ -102,7 +291,7 @@ impl Player {
             displacement_x = 0.0;
         }
         if rel_y + displacement_y > 0.75 && self.vel.1 > 0.0 && (
-            !maze.is_cell_open(cell_x, cell_y, 2) || 
+            !is_open(Direction::PosY) || 
             rel_x < 0.25 || 
             rel_x > 0.75
         ) {
@@ -110,7 +299,7 @@ impl Player {
             displacement_y = 0.0;
         }
         if rel_y + displacement_y < 0.25 && self.vel.1 < 0.0 && (
-            !maze.is_cell_open(cell_x, cell_y, 3) || 
+            !is_open(Direction::NegY) || 
             rel_x < 0.25 || 
             rel_x > 0.75
         ) {
@@ -9,11 +9,25 @@ extern crate nalgebra_glm as glm;
 mod renderer;
 mod cfile;
 mod mazegen;
+mod mazealgo;
+mod mazesolve;
+mod mazefile;
+mod mazemask;
+mod mazestats;
+mod mazeexport;
+mod mazerooms;
+mod mazeweave;
+mod mazelocks;
+mod mazeplace;
+mod mazegrid;
+mod maze3;
 mod mazemesh;
 mod ctr;
 mod game;
 
 use glfw::Context;
+use rand::SeedableRng;
+use mazegen::MazeGraph;
 use renderer::mesh;
 use core::cell::RefCell;
 
@@ -68,25 +82,112 @@ fn main() {
     let uniform_mvp = unsafe {
         prog.get_uniform_loc(std::ffi::CString::new("MVP").expect("NO!"))
     };
+    let uniform_model = unsafe {
+        prog.get_uniform_loc(std::ffi::CString::new("Model").expect("NO!"))
+    };
+    let uniform_light = unsafe {
+        prog.get_uniform_loc(std::ffi::CString::new("lightSource").expect("NO!"))
+    };
+    let uniform_viewer = unsafe {
+        prog.get_uniform_loc(std::ffi::CString::new("viewer").expect("NO!"))
+    };
 
     // Default background color
     unsafe { 
         gl::ClearColor(0.6, 0.6, 0.6, 1.0); 
     }
 
-    // Generate maze
-    let mut maze: mazegen::Maze2 = mazegen::Maze2::empty(15, 15);
-    maze.generate();
-    
-    // Create player
-    let mut player = game::Player::new((7.5, 7.5, 0.5));
-
-    // Create meshes
+    // Load maze from the file given as first argument, or generate it.
+    // A mask file as first argument gives the maze its shape. Seed,
+    // algorithm, braid fraction, wrapping axes, number of rooms, weave
+    // density, number of locked doors and layout can be given as the
+    // next arguments. The layout "floors", or "floors4" and so on, makes
+    // a maze of that many floors, three by default. "hex", "tri" and
+    // "polar" make a maze on that grid
+    let args: Vec<String> = std::env::args().collect();
+    let mask: Option<mazemask::MazeMask> = args.get(1)
+        .filter(|path| mazemask::is_mask_path(path))
+        .map(|path| {
+            mazemask::MazeMask::load(path).expect("Failed to load mask.")
+                .largest_region()
+        });
+    let args: &[String] = if mask.is_some() { &args[1..] } else { &args };
+    let algorithm: &str = args.get(2).map_or("prim", |s| s.as_str());
+    let rooms: usize = args.get(5)
+        .and_then(|s| s.parse::<usize>().ok())
+        .unwrap_or(0);
+    let weave: f64 = args.get(6)
+        .and_then(|s| s.parse::<f64>().ok())
+        .unwrap_or(0.0);
+    let braid: f64 = args.get(3)
+        .and_then(|s| s.parse::<f64>().ok())
+        .unwrap_or(0.0);
+    // The generator asked for, then the ones searched along with it for
+    // an easy, medium or hard maze, all with the same rooms, weave and
+    // braiding
+    let mut generators: Vec<(&str, Configured)> = Vec::new();
+    for &name in [algorithm, "prim", "kruskal", "backtracker"].iter() {
+        if generators.iter().all(|&(other, _)| other != name) {
+            let inner = mazealgo::generator_by_name(name).unwrap_or_else(|| {
+                panic!("Unknown algorithm {}.", name)
+            });
+            generators.push((name, configure(inner, rooms, weave, braid)));
+        }
+    }
+    let generator = &generators[0].1;
+    let seed: u64 = args.get(1)
+        .and_then(|s| s.parse::<u64>().ok())
+        .unwrap_or_else(rand::random);
+    let layout: &str = args.get(8).map_or("", |s| s.as_str());
+    let doors: usize = args.get(7)
+        .and_then(|s| s.parse::<usize>().ok())
+        .unwrap_or(0);
     let mut msdata: renderer::mesh::MeshData = renderer::mesh::MeshData::new();
-    let maze_mesh = mazemesh::MazeMesh2::new(&maze, &mut msdata);
-    let diamond_mesh = mazemesh::diamond(&mut msdata, 14.5, 14.5, 0.5, 0.2);
-    
-    maze.draw_text();
+    let mut heat: bool = false;
+    // Only looked up for the layouts that need it, as some algorithms
+    // only work on flat mazes
+    let graph_generator = || -> Box<dyn mazealgo::GraphGenerator> {
+        return mazealgo::graph_generator_by_name(algorithm).unwrap_or_else(|| {
+            panic!("Unknown algorithm {} for the {} layout.", algorithm, layout)
+        });
+    };
+    let mut world: World = if layout == "hex" {
+        grid_world(mazegrid::HexGrid::new(15, 15), &*graph_generator(), seed)
+    } else if layout == "tri" {
+        grid_world(mazegrid::TriGrid::new(29, 15), &*graph_generator(), seed)
+    } else if layout == "polar" {
+        grid_world(mazegrid::PolarGrid::new(8), &*graph_generator(), seed)
+    } else if layout.starts_with("floors") {
+        let floors: usize = layout["floors".len()..].parse::<usize>().unwrap_or(3).max(1);
+        let mut maze = maze3::Maze3::empty(15, 15, floors);
+        maze.generate_using(&*graph_generator(), 2, seed).expect("Floors too small for stairwells.");
+        maze.draw_text();
+        let mesh = mazemesh::MazeMesh3::new(&maze);
+        World::Floors(maze, mesh)
+    } else {
+        let maze: mazegen::Maze2 = match args.get(1) {
+            Some(path) if mask.is_none() && std::path::Path::new(path).is_file() => {
+                mazegen::Maze2::load(path).expect("Failed to load maze.")
+            }
+            _ => {
+                let wrap: &str = args.get(4).map_or("", |s| s.as_str());
+                let mut maze = match mask {
+                    Some(mask) => mazegen::Maze2::masked(mask),
+                    None => mazegen::Maze2::empty(15, 15)
+                };
+                maze.set_wrap(wrap.contains('x'), wrap.contains('y'));
+                maze.generate_using(generator, seed);
+                maze
+            }
+        };
+        maze.draw_text();
+        // Place start, goal and locked doors, and create meshes
+        let level = Level::new(&maze, doors, heat, &mut msdata);
+        World::Flat(maze, level)
+    };
+
+    // Create player
+    let mut player = game::Player::new(world.start_pos());
 
     // Setup meshes VAO
     unsafe {
@@ -100,6 +201,8 @@ fn main() {
         gl::DepthFunc(gl::LESS);
     }
 
+    let mut growth: Option<Growth> = None;
+
     // Time tracking
     let mut old_time = glfw.get_time();
     let mut count: u32 = 0;
@@ -111,12 +214,37 @@ fn main() {
 
         glfw.poll_events();
         for (_, event) in glfw::flush_messages(&events) {
+            if let World::Flat(ref mut maze, ref mut level) = world {
+                // Other keys wait until a growing maze is done
+                if growth.is_none() {
+                    handle_flat_event(maze, level, &generators, doors, &mut heat, &mut player, &mut msdata, &event);
+                }
+                // Watch a new maze grow with Prim's algorithm
+                if let glfw::WindowEvent::Key(glfw::Key::G, _, glfw::Action::Press, _) = event {
+                    if growth.is_none() {
+                        growth = Some(Growth::start(maze, level, rand::random(), &mut msdata));
+                    }
+                }
+            }
             handle_window_event(&mut window, event);
         }
 
         let current_time = glfw.get_time();
         let dt: f32 = (current_time - old_time) as f32;
 
+        // Grow the maze some more, and set up the level once it is done
+        let mut grown = false;
+        if let (World::Flat(maze, level), Some(growth)) = (&mut world, &mut growth) {
+            grown = growth.advance(maze, level, dt);
+        }
+        if grown {
+            if let (World::Flat(maze, level), Some(growth)) = (&mut world, growth.take()) {
+                *maze = growth.steps.into_maze();
+                level.rebuild(maze, doors, heat, &mut msdata);
+                player.respawn(level.start_pos());
+            }
+        }
+
         unsafe { gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT); }
 
         let (win_width, win_height) = window.get_size();
@@ -126,10 +254,11 @@ fn main() {
         }
 
         player.accept_input(&mut window, dt);
-        player.update_in_maze(&maze, dt);
+        world.update_player(&mut player, dt);
 
         let player_pos: (f32, f32, f32) = player.get_pos();
         let player_look: (f32, f32, f32) = player.get_look_vec();
+        let light_pos: (f32, f32, f32) = player.get_light_pos();
 
         let mproj: glm::Mat4 = glm::perspective(
             (win_width as f32) / (win_height as f32), 
@@ -157,10 +286,17 @@ fn main() {
                 gl::FALSE, 
                 glm::value_ptr(&mvp).as_ptr() as *const gl::types::GLfloat
             );
+            gl::UniformMatrix4fv(
+                uniform_model,
+                1,
+                gl::FALSE,
+                glm::value_ptr(&mmodel).as_ptr() as *const gl::types::GLfloat
+            );
+            gl::Uniform3f(uniform_light, light_pos.0, light_pos.1, light_pos.2);
+            gl::Uniform3f(uniform_viewer, player_pos.0, player_pos.1, player_pos.2);
         }
         
-        maze_mesh.render();
-        diamond_mesh.render();
+        world.render(&player);
 
         window.swap_buffers();
         old_time = current_time;
@@ -172,6 +308,426 @@ fn main() {
     }
 }
 
+// What the player walks through: a flat maze ready to play, a maze of
+// several floors, or the walls of a maze on another grid and where it
+// starts.
+enum World {
+    Flat(mazegen::Maze2, Level),
+    Floors(maze3::Maze3, mazemesh::MazeMesh3),
+    Grid(Vec<Vec<(f32, f32)>>, mazemesh::GridMazeMesh, (f32, f32))
+}
+
+// Generate a maze on a grid, starting in the middle of its first cell.
+fn grid_world<G: mazegrid::Grid>(
+    grid: G,
+    generator: &dyn mazealgo::GraphGenerator,
+    seed: u64
+) -> World {
+
+    let mut maze = mazegrid::GridMaze::empty(grid);
+    maze.generate_using(generator, seed);
+    maze.draw_text();
+    let mesh = mazemesh::GridMazeMesh::new(&maze);
+    let corners: Vec<(f32, f32)> = maze.borrow_grid().sides(0).into_iter()
+        .flat_map(|side| side.points)
+        .collect();
+    let start = corners.iter().fold((0.0, 0.0), |sum, p| (sum.0 + p.0, sum.1 + p.1));
+    let start = (start.0 / corners.len() as f32, start.1 / corners.len() as f32);
+    return World::Grid(maze.walls(), mesh, start);
+
+}
+
+impl World {
+
+    fn start_pos(&self) -> (f32, f32, f32) {
+        return match self {
+            World::Flat(_, level) => level.start_pos(),
+            World::Floors(_, _) => (0.5, 0.5, 0.5),
+            World::Grid(_, _, start) => (start.0, start.1, 0.5)
+        };
+    }
+
+    fn update_player(
+        &self,
+        player: &mut game::Player,
+        dt: f32
+    ) {
+        match self {
+            World::Flat(maze, level) => player.update_in_maze(maze, &level.locks, dt),
+            World::Floors(maze, _) => player.update_in_maze3(maze, dt),
+            World::Grid(walls, _, _) => player.update_in_walls(walls, dt)
+        }
+    }
+
+    fn render(
+        &self,
+        player: &game::Player
+    ) {
+        match self {
+            World::Flat(_, level) => level.render(player),
+            World::Floors(_, mesh) => mesh.render(),
+            World::Grid(_, mesh, _) => mesh.render()
+        }
+    }
+
+}
+
+// Generation events shown per second while a maze grows.
+const GROWTH_RATE: f32 = 400.0;
+
+// Seeds tried for an easy, medium or hard maze before giving up.
+const TARGET_ATTEMPTS: usize = 20;
+
+// Generator with the rooms, weave and braiding given on the command
+// line around it.
+type Configured = mazealgo::Braided<Box<dyn mazegen::MazeGenerator>>;
+
+fn configure(
+    inner: Box<dyn mazegen::MazeGenerator>,
+    rooms: usize,
+    weave: f64,
+    braid: f64
+) -> Configured {
+
+    let mut generator = inner;
+    if rooms > 0 {
+        generator = Box::new(mazerooms::Rooms {
+            inner: generator,
+            count: rooms,
+            min_size: 2,
+            max_size: 4
+        });
+    }
+    if weave > 0.0 {
+        generator = Box::new(mazeweave::Woven {
+            inner: generator,
+            density: weave
+        });
+    }
+    return mazealgo::Braided { inner: generator, fraction: braid };
+
+}
+
+// A new maze shown while it is being generated. The steps own the maze
+// being generated and are kept from frame to frame. The maze of the
+// world is emptied at the start and gets each passage as it is carved,
+// so the player walks the maze as shown.
+struct Growth {
+    steps: mazegen::PrimSteps<mazegen::Maze2, rand_chacha::ChaCha8Rng>,
+    events: f32     // Events due, but not taken yet
+}
+
+impl Growth {
+
+    fn start(
+        maze: &mut mazegen::Maze2,
+        level: &mut Level,
+        seed: u64,
+        msdata: &mut mesh::MeshData
+    ) -> Growth {
+
+        maze.reset();
+        level.show_unfinished(maze, msdata);
+        println!("Growing a maze with Prim's algorithm, seed {}.", seed);
+        return Growth {
+            steps: maze.clone().into_steps(seed),
+            events: 0.0
+        };
+
+    }
+
+    // Take the events due after `dt` more seconds, and rebuild the maze
+    // mesh if any passage was carved. True once the maze is done.
+    fn advance(
+        &mut self,
+        maze: &mut mazegen::Maze2,
+        level: &mut Level,
+        dt: f32
+    ) -> bool {
+
+        self.events += GROWTH_RATE * dt;
+        let mut carved = false;
+        while self.events >= 1.0 {
+            self.events -= 1.0;
+            match self.steps.next() {
+                Some(mazegen::GenEvent::Carve(x, y, dir)) => {
+                    maze.carve_cell(x, y, dir);
+                    carved = true;
+                }
+                Some(_) => {}
+                None => break
+            }
+        }
+        if carved {
+            level.maze_mesh.rebuild(maze, None);
+        }
+        return self.steps.is_done();
+
+    }
+
+}
+
+// A maze made ready to play: start, goal and locked doors placed in it,
+// and its meshes.
+struct Level {
+    start: (usize, usize),
+    locks: mazelocks::Locks,
+    maze_mesh: mazemesh::MazeMesh2,
+    diamond_meshes: Vec<mesh::Mesh>,
+    door_meshes: Vec<(usize, mesh::Mesh)>,  // By key colour
+    key_meshes: Vec<(usize, mesh::Mesh)>
+}
+
+impl Level {
+
+    fn new(
+        maze: &mazegen::Maze2,
+        doors: usize,
+        heat: bool,
+        msdata: &mut mesh::MeshData
+    ) -> Level {
+
+        let mut level = Level {
+            start: (0, 0),
+            locks: mazelocks::Locks::new(),
+            maze_mesh: mazemesh::MazeMesh2::empty(mazemesh::MazeStyle::new()),
+            diamond_meshes: Vec::new(),
+            door_meshes: Vec::new(),
+            key_meshes: Vec::new()
+        };
+        level.rebuild(maze, doors, heat, msdata);
+        return level;
+
+    }
+
+    // Set up a maze with up to `doors` locked doors, tinted by distance
+    // from the start if `heat` is set. The maze mesh is rebuilt in
+    // place, other meshes of the previous level in `msdata` are dropped.
+    fn rebuild(
+        &mut self,
+        maze: &mazegen::Maze2,
+        doors: usize,
+        heat: bool,
+        msdata: &mut mesh::MeshData
+    ) {
+
+        // Start at one end of the longest path, and put the goal in the
+        // dead end farthest from it, if there are dead ends at all
+        let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(maze.get_seed().unwrap_or(0));
+        let (start, farthest, _) = maze.farthest_pair();
+        let mut rules = mazeplace::SpawnRules::new();
+        rules.kinds = vec![mazeplace::CellKind::DeadEnd];
+        let goal = maze.place_spawns(start, 1, &rules, &mut rng).first()
+            .copied()
+            .unwrap_or(farthest);
+
+        // Lock doors on the way to the goal. If no placement leaves the
+        // goal reachable, try again with fewer doors, down to none.
+        let mut locks = mazelocks::Locks::new();
+        'placing: for count in (1..doors + 1).rev() {
+            for _ in 0..10 {
+                let placed = mazelocks::Locks::place(maze, count, start, goal, &mut rng);
+                if placed.pickup_order(maze, start, goal).is_some() {
+                    locks = placed;
+                    break 'placing;
+                }
+            }
+        }
+
+        // Create meshes
+        msdata.clear();
+        let colours = mazemesh::DistanceColours::new(start);
+        self.maze_mesh.rebuild(maze, if heat { Some(&colours) } else { None });
+        let diamond_meshes: Vec<mesh::Mesh> = mazemesh::wrap_offsets(maze).iter()
+            .map(|&(dx, dy)| {
+                mazemesh::diamond(
+                    msdata,
+                    goal.0 as f32 + 0.5 + dx,
+                    goal.1 as f32 + 0.5 + dy,
+                    0.5,
+                    0.2
+                )
+            })
+            .collect();
+        let mut door_meshes: Vec<(usize, mesh::Mesh)> = Vec::new();
+        let mut key_meshes: Vec<(usize, mesh::Mesh)> = Vec::new();
+        let style = self.maze_mesh.get_style();
+        for &(dx, dy) in mazemesh::wrap_offsets(maze).iter() {
+            for door in locks.doors.iter() {
+                door_meshes.push((door.colour, mazemesh::door(
+                    msdata,
+                    door.x as f32 + dx,
+                    door.y as f32 + dy,
+                    door.dir,
+                    style.wall_thickness / 2.0,
+                    style.wall_height,
+                    mazemesh::key_colour(door.colour)
+                )));
+            }
+            for (colour, &(x, y)) in locks.keys.iter().enumerate() {
+                key_meshes.push((colour, mazemesh::gem(
+                    msdata,
+                    x as f32 + 0.5 + dx,
+                    y as f32 + 0.5 + dy,
+                    0.4,
+                    0.1,
+                    mazemesh::key_colour(colour)
+                )));
+            }
+        }
+
+        self.start = start;
+        self.locks = locks;
+        self.diamond_meshes = diamond_meshes;
+        self.door_meshes = door_meshes;
+        self.key_meshes = key_meshes;
+
+    }
+
+    // Show a maze that is still being generated, without anything
+    // placed in it yet.
+    fn show_unfinished(
+        &mut self,
+        maze: &mazegen::Maze2,
+        msdata: &mut mesh::MeshData
+    ) {
+
+        msdata.clear();
+        self.maze_mesh.rebuild(maze, None);
+        self.locks = mazelocks::Locks::new();
+        self.diamond_meshes.clear();
+        self.door_meshes.clear();
+        self.key_meshes.clear();
+
+    }
+
+    fn start_pos(&self) -> (f32, f32, f32) {
+        return (self.start.0 as f32 + 0.5, self.start.1 as f32 + 0.5, 0.5);
+    }
+
+    // Draw the maze, the goal and the doors and keys the player has not
+    // unlocked yet.
+    fn render(
+        &self,
+        player: &game::Player
+    ) {
+
+        self.maze_mesh.render();
+        for diamond_mesh in self.diamond_meshes.iter() {
+            diamond_mesh.render();
+        }
+        for (colour, mesh) in self.door_meshes.iter().chain(self.key_meshes.iter()) {
+            if !player.has_key(*colour) {
+                mesh.render();
+            }
+        }
+
+    }
+
+}
+
+// Keys that act on a flat maze: export, new maze, heat map and ceiling.
+fn handle_flat_event(
+    maze: &mut mazegen::Maze2,
+    level: &mut Level,
+    generators: &[(&str, Configured)],
+    doors: usize,
+    heat: &mut bool,
+    player: &mut game::Player,
+    msdata: &mut mesh::MeshData,
+    event: &glfw::WindowEvent
+) {
+
+    if let glfw::WindowEvent::Key(glfw::Key::P, _, glfw::Action::Press, _) = *event {
+        export_maze(maze);
+    }
+    // New maze of the same size, or 5 cells larger or smaller
+    let size = maze.get_size();
+    let new_size = match *event {
+        glfw::WindowEvent::Key(glfw::Key::N, _, glfw::Action::Press, _) => {
+            Some(size)
+        }
+        glfw::WindowEvent::Key(glfw::Key::Equal, _, glfw::Action::Press, _) => {
+            Some((size.0 + 5, size.1 + 5))
+        }
+        glfw::WindowEvent::Key(glfw::Key::Minus, _, glfw::Action::Press, _)
+            if size.0 > 5 && size.1 > 5 => {
+            Some((size.0 - 5, size.1 - 5))
+        }
+        _ => None
+    };
+    if let Some(new_size) = new_size {
+        if new_size != size {
+            maze.resize(new_size.0, new_size.1);
+        }
+        let seed: u64 = rand::random();
+        maze.regenerate_using(&generators[0].1, seed);
+        println!("New {}x{} maze: {}, seed {}.", new_size.0, new_size.1, generators[0].0, seed);
+        level.rebuild(maze, doors, *heat, msdata);
+        player.respawn(level.start_pos());
+    }
+    // New maze of the same size, easy, medium or hard
+    let cells = maze.cell_count();
+    let target = match *event {
+        glfw::WindowEvent::Key(glfw::Key::Num1, _, glfw::Action::Press, _) => {
+            Some(("easy", mazestats::MazeTarget::easy(cells)))
+        }
+        glfw::WindowEvent::Key(glfw::Key::Num2, _, glfw::Action::Press, _) => {
+            Some(("medium", mazestats::MazeTarget::medium(cells)))
+        }
+        glfw::WindowEvent::Key(glfw::Key::Num3, _, glfw::Action::Press, _) => {
+            Some(("hard", mazestats::MazeTarget::hard(cells)))
+        }
+        _ => None
+    };
+    if let Some((difficulty, target)) = target {
+        let searched: Vec<&dyn mazegen::MazeGenerator> = generators.iter()
+            .map(|(_, generator)| generator as &dyn mazegen::MazeGenerator)
+            .collect();
+        match maze.generate_to_target(&searched, &target, rand::random(), TARGET_ATTEMPTS) {
+            Some((seed, i)) => {
+                println!("Found a {} maze: {}, seed {}.", difficulty, generators[i].0, seed);
+            }
+            None => {
+                println!(
+                    "No {} maze found in {} seeds, keeping the last one tried (seed {}).",
+                    difficulty,
+                    TARGET_ATTEMPTS,
+                    maze.get_seed().unwrap_or(0)
+                );
+            }
+        }
+        level.rebuild(maze, doors, *heat, msdata);
+        player.respawn(level.start_pos());
+    }
+    // Tint the maze by distance from the start, or not
+    if let glfw::WindowEvent::Key(glfw::Key::H, _, glfw::Action::Press, _) = *event {
+        *heat = !*heat;
+        level.rebuild(maze, doors, *heat, msdata);
+    }
+    // Put a ceiling over the maze, or take it off
+    if let glfw::WindowEvent::Key(glfw::Key::C, _, glfw::Action::Press, _) = *event {
+        let mut style = level.maze_mesh.get_style().clone();
+        style.ceiling = !style.ceiling;
+        level.maze_mesh.set_style(style);
+        level.rebuild(maze, doors, *heat, msdata);
+    }
+
+}
+
+// Save the maze with its solution in the working directory, for
+// printing or attaching to bug reports.
+fn export_maze(maze: &mazegen::Maze2) {
+
+    let mut style = mazeexport::ExportStyle::new();
+    style.solution = true;
+    match maze.save_svg("maze.svg", &style).and(maze.save_png("maze.png", &style)) {
+        Ok(()) => println!("Saved maze.svg and maze.png."),
+        Err(err) => println!("Failed to export maze: {:?}", err)
+    }
+
+}
+
 fn handle_window_event(window: &mut glfw::Window, event: glfw::WindowEvent) {
 
     match event {
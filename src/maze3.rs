@@ -0,0 +1,450 @@
+// Multi-floor mazes.
+//
+// Floors are stacked along z, one unit apart. Besides the four 2D
+// directions, cells use 4 = +z (up) and 5 = -z (down). Floors are
+// connected by stairwells: a ramp rising along +x through one cell
+// column, entered from -x on the lower floor and left towards +x on the
+// upper floor.
+
+extern crate rand;
+extern crate rand_chacha;
+
+use rand::SeedableRng;
+use rand::RngCore;
+use rand_chacha::ChaCha8Rng;
+use crate::mazegen::*;
+use crate::mazealgo::GraphGenerator;
+
+// Why the floors of a maze could not be joined.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StairsError {
+    NoRoom(usize)   // No stairwell fits from this floor to the next
+}
+
+pub struct Maze3 {
+    generated: bool,
+    seed: Option<u64>,
+    size: (usize, usize, usize),
+    data: Vec<MazeCell2>
+}
+
+// One floor of a `Maze3` seen as a graph, leaving out stairwell cells.
+struct FloorGraph<'a> {
+    maze: &'a mut Maze3,
+    z: usize,
+    cells: Vec<(usize, usize)>,
+    index: Vec<Option<usize>>
+}
+
+impl<'a> FloorGraph<'a> {
+
+    fn new(
+        maze: &'a mut Maze3,
+        z: usize,
+        skip: &Vec<(usize, usize)>
+    ) -> FloorGraph<'a> {
+
+        let (size_x, size_y, _) = maze.get_size();
+        let mut cells: Vec<(usize, usize)> = Vec::new();
+        let mut index: Vec<Option<usize>> = vec![None; size_x * size_y];
+        for x in 0..size_x {
+            for y in 0..size_y {
+                if !skip.contains(&(x, y)) {
+                    index[x * size_y + y] = Some(cells.len());
+                    cells.push((x, y));
+                }
+            }
+        }
+        return FloorGraph {
+            maze: maze,
+            z: z,
+            cells: cells,
+            index: index
+        };
+
+    }
+
+}
+
+impl<'a> MazeGraph for FloorGraph<'a> {
+
+    fn cell_count(&self) -> usize {
+        return self.cells.len();
+    }
+
+    fn adjacent(
+        &self,
+        cell: usize
+    ) -> Adjacent {
+
+        let (x, y) = self.cells[cell];
+        let size_y = self.maze.get_size().1;
+        let mut cells = Adjacent::new();
+        for dir in 0..4 {
+            if let Some((nx, ny, _)) = self.maze.neighbour(x, y, self.z, dir) {
+                if let Some(next) = self.index[nx * size_y + ny] {
+                    cells.push(dir, next);
+                }
+            }
+        }
+        return cells;
+
+    }
+
+    fn is_linked(
+        &self,
+        cell: usize,
+        slot: u8
+    ) -> bool {
+        let (x, y) = self.cells[cell];
+        return self.maze.is_cell_open(x, y, self.z, slot);
+    }
+
+    fn link(
+        &mut self,
+        cell: usize,
+        slot: u8
+    ) {
+        let (x, y) = self.cells[cell];
+        self.maze.carve(x, y, self.z, slot);
+    }
+
+}
+
+impl Maze3 {
+
+    pub fn empty(
+        size_x: usize,
+        size_y: usize,
+        size_z: usize
+    ) -> Maze3 {
+
+        let mut maze = Maze3 {
+            generated: false,
+            seed: None,
+            size: (size_x, size_y, size_z),
+            data: Vec::new()
+        };
+        for _ in 0..size_x * size_y * size_z {
+            maze.data.push(MazeCell2::new());
+        }
+        return maze;
+
+    }
+
+    fn index(
+        &self,
+        x: usize,
+        y: usize,
+        z: usize
+    ) -> usize {
+        return (z * self.size.0 + x) * self.size.1 + y;
+    }
+
+    pub fn get_size(&self) -> (usize, usize, usize) {
+        return self.size;
+    }
+
+    pub fn get_seed(&self) -> Option<u64> {
+        return self.seed;
+    }
+
+    pub fn get_cell_val(
+        &self,
+        x: usize,
+        y: usize,
+        z: usize
+    ) -> u8 {
+        return self.data[self.index(x, y, z)].val();
+    }
+
+    pub fn is_cell_open(
+        &self,
+        x: usize,
+        y: usize,
+        z: usize,
+        dir: u8
+    ) -> bool {
+        return self.data[self.index(x, y, z)].is_open(dir);
+    }
+
+    // Position of the adjacent cell in direction `dir`, if it lies
+    // inside the maze.
+    pub fn neighbour(
+        &self,
+        x: usize,
+        y: usize,
+        z: usize,
+        dir: u8
+    ) -> Option<(usize, usize, usize)> {
+        match dir {
+            0 if x + 1 < self.size.0 => Some((x + 1, y, z)),
+            1 if x > 0 => Some((x - 1, y, z)),
+            2 if y + 1 < self.size.1 => Some((x, y + 1, z)),
+            3 if y > 0 => Some((x, y - 1, z)),
+            4 if z + 1 < self.size.2 => Some((x, y, z + 1)),
+            5 if z > 0 => Some((x, y, z - 1)),
+            _ => None
+        }
+    }
+
+    // Open the passage between a cell and its neighbour in direction
+    // `dir`, on both sides.
+    pub fn carve(
+        &mut self,
+        x: usize,
+        y: usize,
+        z: usize,
+        dir: u8
+    ) {
+        if let Some((nx, ny, nz)) = self.neighbour(x, y, z, dir) {
+            let (a, b) = (self.index(x, y, z), self.index(nx, ny, nz));
+            self.data[a].open(dir);
+            self.data[b].open(opposite_dir(dir));
+        }
+    }
+
+    // Lower cells of the stairwells from floor z up to floor z + 1.
+    pub fn stairs(
+        &self,
+        z: usize
+    ) -> Vec<(usize, usize)> {
+
+        let mut cells: Vec<(usize, usize)> = Vec::new();
+        for x in 0..self.size.0 {
+            for y in 0..self.size.1 {
+                if self.is_cell_open(x, y, z, 4) {
+                    cells.push((x, y));
+                }
+            }
+        }
+        return cells;
+
+    }
+
+    // A single floor as a flat maze, without the up/down passages.
+    pub fn floor(
+        &self,
+        z: usize
+    ) -> Maze2 {
+
+        let mut floor = Maze2::empty(self.size.0, self.size.1);
+        for x in 0..self.size.0 {
+            for y in 0..self.size.1 {
+                for dir in 0..4 {
+                    if self.is_cell_open(x, y, z, dir) {
+                        floor.borrow_cell_mut(x, y).open(dir);
+                    }
+                }
+            }
+        }
+        floor.set_generated(None);
+        return floor;
+
+    }
+
+    // Generate maze from a seed. Each floor is generated with the given
+    // algorithm, and each pair of floors is joined by up to `stairs`
+    // stairwells.
+    pub fn generate_using<G: GraphGenerator + ?Sized>(
+        &mut self,
+        generator: &G,
+        stairs: usize,
+        seed: u64
+    ) -> Result<(), StairsError> {
+
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        self.generate_using_rng(generator, stairs, &mut rng)?;
+        self.seed = Some(seed);
+        return Ok(());
+
+    }
+
+    // Generate maze with the given algorithm and random number generator.
+    // Every pair of floors gets one stairwell, and as many more up to
+    // `stairs` as there is room for. Stairwells need floors of at least 3
+    // by 2 cells, and at least 3 by 3 or 5 by 2 with more than two
+    // floors. On smaller floors the maze is left as it was and an error
+    // returned.
+    pub fn generate_using_rng<G: GraphGenerator + ?Sized, R: RngCore>(
+        &mut self,
+        generator: &G,
+        stairs: usize,
+        rng: &mut R
+    ) -> Result<(), StairsError> {
+
+        if self.generated {
+            panic!("Maze already generated!")
+        }
+        let (size_x, size_y, size_z) = self.size;
+        // Narrower floors would be cut in two by a stairwell
+        if size_z > 1 && size_y < 2 {
+            return Err(StairsError::NoRoom(0));
+        }
+
+        // Reserve stairwell columns. A stairwell needs cells on both
+        // sides along x, and keeping stairwells apart ensures the rest of
+        // each floor stays connected.
+        let mut reserved: Vec<Vec<(usize, usize)>> = vec![Vec::new(); size_z];
+        let mut stairwells: Vec<(usize, usize, usize)> = Vec::new();
+        // Whether a stairwell can still be placed apart from one at a
+        // cell, i.e. some column is more than one cell away from it
+        let leaves_room = |c: (usize, usize)| -> bool {
+            return c.0 > 2 || c.0 + 3 < size_x || c.1 > 1 || c.1 + 2 < size_y;
+        };
+        // The first stairwell of every pair of floors is placed before
+        // any extra ones, so extras never take the room it needs. It also
+        // leaves room for the first one of the next pair.
+        for round in 0..stairs.max(1) {
+            for z in 0..size_z.max(1) - 1 {
+                let mut free: Vec<(usize, usize)> = Vec::new();
+                for x in 1..size_x.max(1) - 1 {
+                    for y in 0..size_y {
+                        let near = |cells: &Vec<(usize, usize)>| -> bool {
+                            return cells.iter().any(|c| {
+                                (c.0 as isize - x as isize).abs() <= 1
+                                    && (c.1 as isize - y as isize).abs() <= 1
+                            });
+                        };
+                        if !near(&reserved[z]) && !near(&reserved[z + 1]) {
+                            free.push((x, y));
+                        }
+                    }
+                }
+                if round == 0 && z + 2 < size_z {
+                    free.retain(|&c| leaves_room(c));
+                }
+                if free.len() == 0 {
+                    if round == 0 {
+                        return Err(StairsError::NoRoom(z));
+                    }
+                    continue;
+                }
+                let pos = free[rand_index(rng, free.len())];
+                reserved[z].push(pos);
+                reserved[z + 1].push(pos);
+                stairwells.push((pos.0, pos.1, z));
+            }
+        }
+
+        // Generate the floors around the stairwells
+        for z in 0..size_z {
+            let mut graph = FloorGraph::new(self, z, &reserved[z]);
+            generator.generate_graph(&mut graph, rng);
+        }
+
+        // Attach the stairwells
+        for (x, y, z) in stairwells {
+            self.carve(x, y, z, 1);
+            self.carve(x, y, z, 4);
+            self.carve(x, y, z + 1, 0);
+        }
+        self.generated = true;
+        return Ok(());
+
+    }
+
+    // Render the maze in the `Maze2` text format, one floor after the
+    // other. Stairwells are marked "/\" below and "\/" above.
+    pub fn to_text(&self) -> String {
+
+        let mut text = String::new();
+        if let Some(seed) = self.seed {
+            text.push_str(&format!("Seed: {}\n", seed));
+        }
+        for z in 0..self.size.2 {
+            text.push_str(&format!("Floor {}:\n", z));
+            for x in 0..self.size.0 {
+                for y in 0..self.size.1 {
+                    text.push_str(
+                        if self.is_cell_open(x, y, z, 4) {
+                            "/\\"
+                        } else if self.is_cell_open(x, y, z, 5) {
+                            "\\/"
+                        } else {
+                            "██"
+                        }
+                    );
+                    text.push_str(if self.is_cell_open(x, y, z, 2) { "██" } else { "  " });
+                }
+                text.push('\n');
+                for y in 0..self.size.1 {
+                    text.push_str(if self.is_cell_open(x, y, z, 0) { "██" } else { "  " });
+                    text.push_str("  ");
+                }
+                text.push('\n');
+            }
+        }
+        return text;
+
+    }
+
+    pub fn draw_text(&self) {
+        print!("{}", self.to_text());
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::mazealgo::Kruskal;
+
+    // Cells reachable from the first one, through floors and stairwells.
+    fn reachable_count(maze: &Maze3) -> usize {
+
+        let (size_x, size_y, size_z) = maze.get_size();
+        let mut seen: Vec<bool> = vec![false; size_x * size_y * size_z];
+        let mut stack: Vec<(usize, usize, usize)> = vec![(0, 0, 0)];
+        seen[maze.index(0, 0, 0)] = true;
+        while let Some((x, y, z)) = stack.pop() {
+            for dir in 0..6 {
+                if !maze.is_cell_open(x, y, z, dir) {
+                    continue;
+                }
+                let next = maze.neighbour(x, y, z, dir).unwrap();
+                let i = maze.index(next.0, next.1, next.2);
+                if !seen[i] {
+                    seen[i] = true;
+                    stack.push(next);
+                }
+            }
+        }
+        return seen.iter().filter(|&&s| s).count();
+
+    }
+
+    // The smallest floors stairwells fit on are joined whatever the seed,
+    // and every cell can be reached.
+    #[test]
+    fn small_floors_are_joined() {
+
+        for &(size_x, size_y, size_z) in [(3, 2, 2), (3, 3, 4), (5, 2, 3), (4, 4, 5)].iter() {
+            for seed in 0..50 {
+                let mut maze = Maze3::empty(size_x, size_y, size_z);
+                assert_eq!(maze.generate_using(&Kruskal, 2, seed), Ok(()));
+                for z in 0..size_z - 1 {
+                    assert!(maze.stairs(z).len() >= 1);
+                }
+                assert_eq!(reachable_count(&maze), size_x * size_y * size_z);
+            }
+        }
+
+    }
+
+    #[test]
+    fn floors_too_small_for_stairwells_are_refused() {
+
+        for &(size_x, size_y, size_z) in [(2, 5, 2), (5, 1, 2), (4, 2, 3), (3, 2, 3)].iter() {
+            let mut maze = Maze3::empty(size_x, size_y, size_z);
+            assert!(maze.generate_using(&Kruskal, 1, 3).is_err());
+            assert_eq!(maze.get_seed(), None);
+        }
+        // A single floor needs no stairwell
+        let mut maze = Maze3::empty(2, 1, 1);
+        assert_eq!(maze.generate_using(&Kruskal, 1, 3), Ok(()));
+
+    }
+
+}
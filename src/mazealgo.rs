@@ -0,0 +1,793 @@
+// Maze generation algorithms.
+//
+// Every generator carves a perfect maze (exactly one path between any
+// two cells) into an empty `Maze2`, apart from `Braided` which adds
+// loops on top. They differ in the texture of the maze they produce, so
+// a level can pick the one it wants.
+
+extern crate rand;
+
+use std::collections::VecDeque;
+use rand::Rng;
+use rand::RngCore;
+use crate::mazegen::*;
+
+// An algorithm that only relies on cells and their adjacency, so it
+// works on any grid topology.
+pub trait GraphGenerator {
+
+    fn generate_graph(
+        &self,
+        maze: &mut dyn MazeGraph,
+        rng: &mut dyn RngCore
+    );
+
+}
+
+// Graph generators carve through an inherent `carve` that is generic
+// over the maze, so on rectangular mazes the adjacency calls are inlined
+// rather than made through `dyn`.
+macro_rules! impl_generators {
+
+    ( $( $t:ty ),* ) => {
+        $(
+            impl GraphGenerator for $t {
+
+                fn generate_graph(
+                    &self,
+                    maze: &mut dyn MazeGraph,
+                    rng: &mut dyn RngCore
+                ) {
+                    self.carve(maze, rng);
+                }
+
+            }
+
+            impl MazeGenerator for $t {
+
+                fn generate(
+                    &self,
+                    maze: &mut Maze2,
+                    rng: &mut dyn RngCore
+                ) {
+                    self.carve(maze, rng);
+                }
+
+            }
+        )*
+    }
+
+}
+
+impl_generators!(
+    RecursiveBacktracker,
+    Kruskal,
+    Wilson,
+    AldousBroder,
+    HuntAndKill,
+    GrowingTree
+);
+
+// Pick a random adjacent cell of a cell.
+fn rand_adjacent<G: MazeGraph + ?Sized>(
+    maze: &G,
+    rng: &mut dyn RngCore,
+    cell: usize
+) -> (u8, usize) {
+
+    let cells = maze.adjacent(cell);
+    return cells[rand_index(rng, cells.len())];
+
+}
+
+// List adjacent cells of a cell that are (or are not) visited.
+fn adjacent_visited<G: MazeGraph + ?Sized>(
+    maze: &G,
+    visited: &Vec<bool>,
+    cell: usize,
+    want_visited: bool
+) -> Adjacent {
+
+    let mut cells = Adjacent::new();
+    for &(slot, next) in maze.adjacent(cell).iter() {
+        cells.push_if(slot, next, visited[next] == want_visited);
+    }
+    return cells;
+
+}
+
+// Recursive backtracker (randomized depth-first search). Produces long,
+// winding corridors with few dead ends.
+pub struct RecursiveBacktracker;
+
+impl RecursiveBacktracker {
+
+    fn carve<G: MazeGraph + ?Sized>(
+        &self,
+        maze: &mut G,
+        rng: &mut dyn RngCore
+    ) {
+
+        GrowingTree::new(CellPicker::Newest).carve(maze, rng);
+
+    }
+
+}
+
+// Randomized Kruskal's algorithm. Gives every wall a random weight and
+// joins disjoint sets of cells through the lightest walls first.
+//
+// Instead of sorting the walls, the sets are merged in rounds where
+// every set joins the set behind its lightest wall (Boruvka's
+// algorithm). That carves exactly the maze Kruskal's order would, but
+// only ever scans the walls in order, which is several times faster
+// on big mazes.
+pub struct Kruskal;
+
+// Random weight of a wall in its upper half, from a hash so it does not
+// have to be stored. The lower half is left for the wall's index.
+fn wall_weight(
+    seed: u64,
+    wall: u32
+) -> u64 {
+
+    let mut z = seed.wrapping_add((wall as u64).wrapping_mul(0x9E3779B97F4A7C15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    return (z ^ (z >> 31)) & !0xFFFF_FFFF;
+
+}
+
+impl Kruskal {
+
+    fn carve<G: MazeGraph + ?Sized>(
+        &self,
+        maze: &mut G,
+        rng: &mut dyn RngCore
+    ) {
+
+        let count = maze.cell_count();
+        if count > 1 << 28 {
+            panic!("Maze too large for Kruskal's algorithm!")
+        }
+        let seed = rng.next_u64();
+
+        // Index every inner wall once, from its lower numbered cell, as
+        // the cell and slot packed together and the cell behind it
+        let mut walls: Vec<(u32, u32)> = Vec::new();
+        for cell in 0..count {
+            for &(slot, next) in maze.adjacent(cell).iter() {
+                if next > cell {
+                    walls.push(((cell as u32) << 4 | slot as u32, next as u32));
+                }
+            }
+        }
+
+        // Set of every cell, which always points straight at the root
+        let mut sets: Vec<u32> = (0..count as u32).collect();
+        let mut roots: Vec<u32> = (0..count as u32).collect();
+        let mut lightest: Vec<u64> = vec![u64::MAX; count];
+        let mut joins: Vec<u32> = Vec::new();
+
+        while walls.len() > 0 {
+            // Find the lightest wall out of every set, and drop the walls
+            // that now lie inside a set. Ties of the random part go to
+            // the wall listed first, which stays the same every round.
+            let mut kept = 0;
+            for i in 0..walls.len() {
+                let (wall, next) = walls[i];
+                let a = sets[(wall >> 4) as usize] as usize;
+                let b = sets[next as usize] as usize;
+                if a != b {
+                    let weight = wall_weight(seed, wall) | kept as u64;
+                    lightest[a] = lightest[a].min(weight);
+                    lightest[b] = lightest[b].min(weight);
+                    walls[kept] = walls[i];
+                    kept += 1;
+                }
+            }
+            walls.truncate(kept);
+
+            // Sets with no walls left are done
+            roots.retain(|&root| lightest[root as usize] != u64::MAX);
+            joins.clear();
+            for &root in roots.iter() {
+                let (wall, next) = walls[lightest[root as usize] as u32 as usize];
+                let a = sets[(wall >> 4) as usize];
+                let b = sets[next as usize];
+                joins.push(a ^ b ^ root);
+            }
+
+            // Join every set to the one behind its lightest wall. When
+            // two sets picked the same wall, the lower one stays a root.
+            for (&root, &join) in roots.iter().zip(joins.iter()) {
+                let weight = lightest[root as usize];
+                if weight != lightest[join as usize] || root > join {
+                    let (wall, _) = walls[weight as u32 as usize];
+                    maze.link((wall >> 4) as usize, (wall & 15) as u8);
+                    sets[root as usize] = join;
+                }
+            }
+            for &root in roots.iter() {
+                lightest[root as usize] = u64::MAX;
+            }
+            roots.retain(|&root| sets[root as usize] == root);
+
+            for cell in 0..count {
+                let set = sets[cell];
+                let mut root = set;
+                while sets[root as usize] != root {
+                    root = sets[root as usize];
+                }
+                sets[set as usize] = root;
+                sets[cell] = root;
+            }
+        }
+
+    }
+
+}
+
+// Wilson's algorithm (loop-erased random walks). Produces an unbiased
+// sample of all possible mazes.
+pub struct Wilson;
+
+impl Wilson {
+
+    fn carve<G: MazeGraph + ?Sized>(
+        &self,
+        maze: &mut G,
+        rng: &mut dyn RngCore
+    ) {
+
+        let count = maze.cell_count();
+        if count == 0 {
+            return;
+        }
+        let mut visited: Vec<bool> = vec![false; count];
+        let mut walk: Vec<(u8, usize)> = vec![(0, 0); count];
+
+        // Seed the maze with one random cell
+        visited[rand_index(rng, count)] = true;
+
+        let mut starts: Vec<usize> = (0..count).collect();
+        shuffle(rng, &mut starts);
+
+        for start in starts {
+            // Walk until the maze is hit. Only the last step taken out
+            // of each cell is kept, which erases any loops.
+            let mut cell = start;
+            while !visited[cell] {
+                walk[cell] = rand_adjacent(maze, rng, cell);
+                cell = walk[cell].1;
+            }
+
+            // Carve the loop-erased walk into the maze
+            cell = start;
+            while !visited[cell] {
+                let (slot, next) = walk[cell];
+                visited[cell] = true;
+                maze.link(cell, slot);
+                cell = next;
+            }
+        }
+
+    }
+
+}
+
+// Aldous-Broder algorithm (plain random walk). Unbiased like Wilson's,
+// but slow to finish on large mazes.
+pub struct AldousBroder;
+
+impl AldousBroder {
+
+    fn carve<G: MazeGraph + ?Sized>(
+        &self,
+        maze: &mut G,
+        rng: &mut dyn RngCore
+    ) {
+
+        let count = maze.cell_count();
+        if count == 0 {
+            return;
+        }
+        let mut visited: Vec<bool> = vec![false; count];
+        let mut cell = rand_index(rng, count);
+        visited[cell] = true;
+        let mut remaining = count - 1;
+
+        while remaining > 0 {
+            let (slot, next) = rand_adjacent(maze, rng, cell);
+            if !visited[next] {
+                maze.link(cell, slot);
+                visited[next] = true;
+                remaining -= 1;
+            }
+            cell = next;
+        }
+
+    }
+
+}
+
+// Eller's algorithm. Builds the maze one row (along y) at a time while
+// tracking which cells of the current row are already connected.
+pub struct Eller {
+    pub join_chance: f64,   // Chance to join adjacent cells in a row
+}
+
+impl Eller {
+
+    pub fn new() -> Eller {
+        return Eller {
+            join_chance: 0.5
+        };
+    }
+
+}
+
+impl MazeGenerator for Eller {
+
+    fn generate(
+        &self,
+        maze: &mut Maze2,
+        rng: &mut dyn RngCore
+    ) {
+
+        let (size_x, size_y) = maze.get_size();
+
+        // Cells of the current row that are connected form a circular
+        // list, in order of y. Sets never cross each other (the maze is
+        // flat), so two adjacent cells are in the same set exactly when
+        // one follows the other, and joining sets is splicing two lists.
+        // Rows run along y, so a row lies in one piece of the cell buffer.
+        let mut left: Vec<usize> = (0..size_y).collect();
+        let mut right: Vec<usize> = (0..size_y).collect();
+
+        for x in 0..size_x {
+            let last_row = x + 1 == size_x;
+            for y in 0..size_y {
+                // Randomly join adjacent cells of different sets. The
+                // last row joins all of them.
+                let next = y + 1;
+                if next < size_y && right[y] != next
+                    && (last_row || rng.gen_bool(self.join_chance)) {
+                    maze.carve_cell(x, y, 2);
+                    let after = right[y];
+                    let before = left[next];
+                    right[y] = next;
+                    left[next] = y;
+                    right[before] = after;
+                    left[after] = before;
+                }
+                if last_row {
+                    continue;
+                }
+
+                // Extend into the next row, or leave the set and start a
+                // new one there. The last cell left in a set always
+                // extends, so every set does at least once.
+                if right[y] != y && rng.gen() {
+                    right[left[y]] = right[y];
+                    left[right[y]] = left[y];
+                    left[y] = y;
+                    right[y] = y;
+                } else {
+                    maze.carve_cell(x, y, 0);
+                }
+            }
+        }
+
+    }
+
+}
+
+// Hunt-and-kill algorithm. Random walks like the recursive backtracker,
+// but scans for a new starting cell instead of backtracking.
+pub struct HuntAndKill;
+
+impl HuntAndKill {
+
+    fn carve<G: MazeGraph + ?Sized>(
+        &self,
+        maze: &mut G,
+        rng: &mut dyn RngCore
+    ) {
+
+        let count = maze.cell_count();
+        if count == 0 {
+            return;
+        }
+        let mut visited: Vec<bool> = vec![false; count];
+        let mut current: Option<usize> = Some(rand_index(rng, count));
+        if let Some(cell) = current {
+            visited[cell] = true;
+        }
+
+        while let Some(cell) = current {
+            // Kill: walk into a random unvisited neighbour
+            let cells = adjacent_visited(maze, &visited, cell, false);
+            if cells.len() > 0 {
+                let (slot, next) = cells[rand_index(rng, cells.len())];
+                maze.link(cell, slot);
+                visited[next] = true;
+                current = Some(next);
+                continue;
+            }
+
+            // Hunt: find an unvisited cell next to the visited area
+            current = None;
+            for hunt in 0..count {
+                if visited[hunt] {
+                    continue;
+                }
+                let cells = adjacent_visited(maze, &visited, hunt, true);
+                if cells.len() > 0 {
+                    let (slot, _) = cells[rand_index(rng, cells.len())];
+                    maze.link(hunt, slot);
+                    visited[hunt] = true;
+                    current = Some(hunt);
+                    break;
+                }
+            }
+        }
+
+    }
+
+}
+
+// Binary tree algorithm. Every cell opens towards +x or +y, which gives
+// long straight corridors along the far edges and a strong diagonal bias.
+pub struct BinaryTree;
+
+impl MazeGenerator for BinaryTree {
+
+    fn generate(
+        &self,
+        maze: &mut Maze2,
+        rng: &mut dyn RngCore
+    ) {
+
+        let (size_x, size_y) = maze.get_size();
+        for x in 0..size_x {
+            for y in 0..size_y {
+                let mut dirs: [u8; 2] = [0; 2];
+                let mut count: usize = 0;
+                if x + 1 < size_x {
+                    dirs[count] = 0;
+                    count += 1;
+                }
+                if y + 1 < size_y {
+                    dirs[count] = 2;
+                    count += 1;
+                }
+                if count > 0 {
+                    maze.carve_cell(x, y, dirs[rand_index(rng, count)]);
+                }
+            }
+        }
+
+    }
+
+}
+
+// Sidewinder algorithm. Carves runs along +x and closes each run by
+// opening one of its cells towards +y. The last row is one corridor.
+pub struct Sidewinder;
+
+impl MazeGenerator for Sidewinder {
+
+    fn generate(
+        &self,
+        maze: &mut Maze2,
+        rng: &mut dyn RngCore
+    ) {
+
+        let (size_x, size_y) = maze.get_size();
+        for y in 0..size_y {
+            let last_row = y + 1 == size_y;
+            let mut run_start: usize = 0;
+            for x in 0..size_x {
+                let close_run = x + 1 == size_x || (!last_row && rng.gen());
+                if close_run {
+                    if !last_row {
+                        let cell = run_start + rand_index(rng, x - run_start + 1);
+                        maze.carve_cell(cell, y, 2);
+                    }
+                    run_start = x + 1;
+                } else {
+                    maze.carve_cell(x, y, 0);
+                }
+            }
+        }
+
+    }
+
+}
+
+// Policy for picking the next cell to grow from in `GrowingTree`.
+#[derive(Clone, Copy, Debug)]
+pub enum CellPicker {
+    Newest,         // Same as the recursive backtracker
+    Oldest,
+    Middle,
+    Random,         // Similar to Prim's
+    Mixed(f64)      // Newest with the given chance, otherwise random
+}
+
+// Growing tree algorithm. Grows the maze from a list of active cells;
+// the picking policy decides its texture.
+pub struct GrowingTree {
+    pub picker: CellPicker
+}
+
+impl GrowingTree {
+
+    pub fn new(
+        picker: CellPicker
+    ) -> GrowingTree {
+        return GrowingTree {
+            picker: picker
+        };
+    }
+
+    fn pick(
+        &self,
+        rng: &mut dyn RngCore,
+        len: usize
+    ) -> usize {
+        return match self.picker {
+            CellPicker::Newest => len - 1,
+            CellPicker::Oldest => 0,
+            CellPicker::Middle => len / 2,
+            CellPicker::Random => rand_index(rng, len),
+            CellPicker::Mixed(chance) => {
+                if rng.gen_bool(chance) {
+                    len - 1
+                } else {
+                    rand_index(rng, len)
+                }
+            }
+        };
+    }
+
+    fn carve<G: MazeGraph + ?Sized>(
+        &self,
+        maze: &mut G,
+        rng: &mut dyn RngCore
+    ) {
+
+        let count = maze.cell_count();
+        if count == 0 {
+            return;
+        }
+        let mut visited: Vec<bool> = vec![false; count];
+        let start = rand_index(rng, count);
+        visited[start] = true;
+        let mut active: VecDeque<usize> = VecDeque::new();
+        active.push_back(start);
+
+        while active.len() > 0 {
+            let index = self.pick(rng, active.len());
+            let cell = active[index];
+            let cells = adjacent_visited(maze, &visited, cell, false);
+            if cells.len() > 0 {
+                let (slot, next) = cells[rand_index(rng, cells.len())];
+                maze.link(cell, slot);
+                visited[next] = true;
+                active.push_back(next);
+            } else {
+                let last = active.len() - 1;
+                match self.picker {
+                    // Order does not matter to a random pick, apart from
+                    // the newest cell staying at the back for mixed ones
+                    CellPicker::Random => {
+                        active.swap_remove_back(index);
+                    }
+                    CellPicker::Mixed(_) if index < last => {
+                        active.swap(index, last - 1);
+                        active.remove(last - 1);
+                    }
+                    _ => {
+                        active.remove(index);
+                    }
+                }
+            }
+        }
+
+    }
+
+}
+
+impl Maze2 {
+
+    // Number of open passages out of a cell.
+    pub fn open_count(
+        &self,
+        x: usize,
+        y: usize
+    ) -> u32 {
+        return self.get_cell_val(x, y).count_ones();
+    }
+
+    // Cells with exactly one open passage.
+    pub fn dead_ends(&self) -> Vec<(usize, usize)> {
+
+        let size = self.get_size();
+        let mut cells: Vec<(usize, usize)> = Vec::new();
+        for x in 0..size.0 {
+            for y in 0..size.1 {
+                if self.open_count(x, y) == 1 {
+                    cells.push((x, y));
+                }
+            }
+        }
+        return cells;
+
+    }
+
+    // Remove roughly `fraction` (0.0 to 1.0) of the dead ends by opening
+    // an extra wall out of each, which adds loops to the maze. Walls
+    // into other dead ends are preferred, removing both at once.
+    pub fn braid<R: Rng + ?Sized>(
+        &mut self,
+        fraction: f64,
+        rng: &mut R
+    ) {
+
+        let fraction = fraction.max(0.0).min(1.0);
+        let mut dead_ends = self.dead_ends();
+        shuffle(rng, &mut dead_ends);
+
+        for (x, y) in dead_ends {
+            // May have been removed along with an earlier one
+            if self.open_count(x, y) != 1 || !rng.gen_bool(fraction) {
+                continue;
+            }
+            let mut closed: Vec<u8> = Vec::new();
+            let mut closed_dead_ends: Vec<u8> = Vec::new();
+            for dir in 0..4 {
+                if self.is_cell_open(x, y, dir) {
+                    continue;
+                }
+                if let Some((nx, ny)) = self.neighbour(x, y, dir) {
+                    closed.push(dir);
+                    if self.open_count(nx, ny) == 1 {
+                        closed_dead_ends.push(dir);
+                    }
+                }
+            }
+            let dirs = if closed_dead_ends.len() > 0 { closed_dead_ends } else { closed };
+            if dirs.len() > 0 {
+                self.carve_cell(x, y, dirs[rand_index(rng, dirs.len())]);
+            }
+        }
+
+    }
+
+}
+
+// Generates a maze with another algorithm, then braids it.
+pub struct Braided<G: MazeGenerator> {
+    pub inner: G,
+    pub fraction: f64
+}
+
+impl<G: MazeGenerator> MazeGenerator for Braided<G> {
+
+    fn generate(
+        &self,
+        maze: &mut Maze2,
+        rng: &mut dyn RngCore
+    ) {
+
+        self.inner.generate(maze, rng);
+        maze.braid(self.fraction, rng);
+
+    }
+
+}
+
+// Look up a generator by name, e.g. for level configuration.
+pub fn generator_by_name(
+    name: &str
+) -> Option<Box<dyn MazeGenerator>> {
+
+    return match name {
+        "prim" => Some(Box::new(Prim)),
+        "backtracker" => Some(Box::new(RecursiveBacktracker)),
+        "kruskal" => Some(Box::new(Kruskal)),
+        "wilson" => Some(Box::new(Wilson)),
+        "aldous-broder" => Some(Box::new(AldousBroder)),
+        "eller" => Some(Box::new(Eller::new())),
+        "hunt-and-kill" => Some(Box::new(HuntAndKill)),
+        "binary-tree" => Some(Box::new(BinaryTree)),
+        "sidewinder" => Some(Box::new(Sidewinder)),
+        "growing-tree" => Some(Box::new(GrowingTree::new(CellPicker::Mixed(0.5)))),
+        _ => None
+    };
+
+}
+
+// Look up a generator that works on any grid topology by name. Prim's
+// is approximated by a growing tree picking random cells.
+pub fn graph_generator_by_name(
+    name: &str
+) -> Option<Box<dyn GraphGenerator>> {
+
+    return match name {
+        "prim" => Some(Box::new(GrowingTree::new(CellPicker::Random))),
+        "backtracker" => Some(Box::new(RecursiveBacktracker)),
+        "kruskal" => Some(Box::new(Kruskal)),
+        "wilson" => Some(Box::new(Wilson)),
+        "aldous-broder" => Some(Box::new(AldousBroder)),
+        "hunt-and-kill" => Some(Box::new(HuntAndKill)),
+        "growing-tree" => Some(Box::new(GrowingTree::new(CellPicker::Mixed(0.5)))),
+        _ => None
+    };
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::mazemask::MazeMask;
+
+    const NAMES: [&str; 10] = [
+        "prim", "backtracker", "kruskal", "wilson", "aldous-broder", "eller",
+        "hunt-and-kill", "binary-tree", "sidewinder", "growing-tree"
+    ];
+
+    // Every generator carves a spanning tree of the active cells: one
+    // passage less than there are cells, and all of them reachable. The
+    // mask cuts through rows and columns, and has a hole in the middle.
+    #[test]
+    fn generators_carve_spanning_trees_on_masked_mazes() {
+
+        let text = "..####..\n.######.\n###..###\n###..###\n########\n.##..##.\n";
+        for &name in NAMES.iter() {
+            let generator = generator_by_name(name).unwrap();
+            for seed in 0..20 {
+                let mask = MazeMask::from_text(text).unwrap();
+                let cells = mask.active_cells().len();
+                let mut maze = Maze2::masked(mask);
+                maze.generate_using(&generator, seed);
+
+                let size = maze.get_size();
+                let mut ends: u32 = 0;
+                for x in 0..size.0 {
+                    for y in 0..size.1 {
+                        ends += maze.open_count(x, y);
+                    }
+                }
+                assert_eq!(ends as usize / 2, cells - 1, "{} with seed {}", name, seed);
+                let reach = maze.reachable(maze.first_cell(), |_, _, _| false);
+                assert_eq!(reach.iter().filter(|&&r| r).count(), cells, "{} with seed {}", name, seed);
+            }
+        }
+
+    }
+
+    // Mazes without cells, or with all of them masked out, are left as
+    // they are.
+    #[test]
+    fn generators_accept_mazes_without_cells() {
+
+        for &name in NAMES.iter() {
+            let generator = generator_by_name(name).unwrap();
+            let mut maze = Maze2::empty(0, 0);
+            maze.generate_using(&generator, 1);
+            let mut maze = Maze2::masked(MazeMask::new(3, 2, vec![false; 6]));
+            maze.generate_using(&generator, 1);
+            assert!(maze.borrow_data().iter().all(|cell| cell.val() == 0), "{}", name);
+        }
+        let mut maze = Maze2::empty(0, 0);
+        let mut steps = maze.generate_steps(1);
+        assert_eq!(steps.next(), None);
+        assert!(steps.is_done());
+
+    }
+
+}
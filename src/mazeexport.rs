@@ -0,0 +1,296 @@
+// Maze export to SVG and PNG.
+//
+// Images are laid out like `draw_text`: x runs down, y runs right. Both
+// formats are drawn from the same list of rectangles, so they match
+// pixel for pixel. Crossings show the walls of the passage on top
+// running across the cell, with the one underneath passing below them.
+
+extern crate png;
+
+use std::io::Write;
+use crate::mazegen::*;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    Png(png::EncodingError),
+}
+
+impl From<std::io::Error> for Error {
+
+    fn from(inp: std::io::Error) -> Self {
+        return Error::Io(inp);
+    }
+
+}
+
+impl From<png::EncodingError> for Error {
+
+    fn from(inp: png::EncodingError) -> Self {
+        return Error::Png(inp);
+    }
+
+}
+
+pub type Colour = (u8, u8, u8);
+
+pub struct ExportStyle {
+    pub cell_size: u32,         // Pixels from one wall to the next
+    pub wall_thickness: u32,    // Pixels
+    pub margin: u32,            // Pixels around the maze
+    pub background: Colour,
+    pub wall: Colour,
+    pub solution: bool,         // Draw the path between start and goal
+    pub path: Colour,
+    pub start: Colour,
+    pub goal: Colour,
+}
+
+impl ExportStyle {
+
+    pub fn new() -> ExportStyle {
+        return ExportStyle {
+            cell_size: 16,
+            wall_thickness: 2,
+            margin: 8,
+            background: (255, 255, 255),
+            wall: (0, 0, 0),
+            solution: false,
+            path: (220, 40, 40),
+            start: (40, 160, 40),
+            goal: (40, 80, 220),
+        };
+    }
+
+}
+
+// Filled rectangle, from (x0, y0) up to but not including (x1, y1), in
+// image pixels.
+struct Rect {
+    x0: u32,
+    y0: u32,
+    x1: u32,
+    y1: u32,
+    colour: Colour
+}
+
+impl Maze2 {
+
+    // Image size and the rectangles to draw, back to front. Start and
+    // goal of the solution are the two cells farthest apart.
+    fn export_rects(
+        &self,
+        style: &ExportStyle
+    ) -> (u32, u32, Vec<Rect>) {
+
+        let size = self.get_size();
+        let (cell, t, m) = (style.cell_size, style.wall_thickness, style.margin);
+        let width = 2 * m + size.1 as u32 * cell + t;
+        let height = 2 * m + size.0 as u32 * cell + t;
+        let mut rects: Vec<Rect> = vec![
+            Rect { x0: 0, y0: 0, x1: width, y1: height, colour: style.background }
+        ];
+
+        // Grid lines at the top left of a cell, and cell centres
+        let line = |x: usize, y: usize| -> (u32, u32) {
+            return (m + y as u32 * cell, m + x as u32 * cell);
+        };
+        let centre = |pos: (usize, usize)| -> (u32, u32) {
+            let (px, py) = line(pos.0, pos.1);
+            return (px + (cell + t) / 2, py + (cell + t) / 2);
+        };
+
+        if style.solution {
+            let (start, goal, _) = self.farthest_pair();
+            let route = self.solve(start, goal).unwrap_or(vec![start]);
+            let w = (cell / 8).max(1);
+            // Maze x runs down the image and y across it
+            let towards = |c: (u32, u32), dir: Direction| -> (u32, u32) {
+                let d = ((cell + t) / 2) as isize;
+                let (dx, dy) = dir.offset();
+                return ((c.0 as isize + dy * d) as u32, (c.1 as isize + dx * d) as u32);
+            };
+            let mut segments: Vec<((u32, u32), (u32, u32))> = Vec::new();
+            for step in route.windows(2) {
+                let (p, q) = (step[0], step[1]);
+                let dist = (p.0 as isize - q.0 as isize).abs()
+                    + (p.1 as isize - q.1 as isize).abs();
+                if dist == 1 {
+                    segments.push((centre(p), centre(q)));
+                } else if let Some(dir) = self.dir_towards(p, q).and_then(Direction::from_u8) {
+                    // Across the seam: out of one edge, in on the other
+                    segments.push((centre(p), towards(centre(p), dir)));
+                    segments.push((centre(q), towards(centre(q), dir.opposite())));
+                }
+            }
+            for (a, b) in segments {
+                rects.push(Rect {
+                    x0: a.0.min(b.0) - w,
+                    y0: a.1.min(b.1) - w,
+                    x1: a.0.max(b.0) + w,
+                    y1: a.1.max(b.1) + w,
+                    colour: style.path
+                });
+            }
+            let r = (cell / 4).max(1);
+            for &(pos, colour) in [(start, style.start), (goal, style.goal)].iter() {
+                let c = centre(pos);
+                rects.push(Rect {
+                    x0: c.0 - r,
+                    y0: c.1 - r,
+                    x1: c.0 + r,
+                    y1: c.1 + r,
+                    colour: colour
+                });
+            }
+        }
+
+        // Walls towards -x and -y belong to the cell, walls towards +x
+        // and +y only on the edge of the maze or mask. Wrapping edges
+        // show the seam on both sides.
+        for x in 0..size.0 {
+            for y in 0..size.1 {
+                if !self.is_cell_active(x, y) {
+                    continue;
+                }
+                let (px, py) = line(x, y);
+                if let Some(over) = self.get_crossing(x, y) {
+                    let q = cell / 4;
+                    for &inset in [q, cell - q].iter() {
+                        let (x0, y0) = if over == 0 { (px + inset, py) } else { (px, py + inset) };
+                        let (x1, y1) = if over == 0 {
+                            (x0 + t, y0 + cell + t)
+                        } else {
+                            (x0 + cell + t, y0 + t)
+                        };
+                        rects.push(Rect {
+                            x0: x0,
+                            y0: y0,
+                            x1: x1,
+                            y1: y1,
+                            colour: style.wall
+                        });
+                    }
+                }
+                for dir in 0..4 {
+                    let edge = match dir {
+                        0 => x + 1 == size.0,
+                        2 => y + 1 == size.1,
+                        _ => true
+                    };
+                    let owned = edge || self.neighbour(x, y, dir).is_none();
+                    if self.is_cell_open(x, y, dir) || !owned {
+                        continue;
+                    }
+                    let (x0, y0) = match dir {
+                        0 => (px, py + cell),
+                        2 => (px + cell, py),
+                        _ => (px, py)
+                    };
+                    let (x1, y1) = if dir < 2 {
+                        (x0 + cell + t, y0 + t)
+                    } else {
+                        (x0 + t, y0 + cell + t)
+                    };
+                    rects.push(Rect {
+                        x0: x0,
+                        y0: y0,
+                        x1: x1,
+                        y1: y1,
+                        colour: style.wall
+                    });
+                }
+            }
+        }
+        return (width, height, rects);
+
+    }
+
+    // Render the maze as an SVG document.
+    pub fn to_svg(
+        &self,
+        style: &ExportStyle
+    ) -> String {
+
+        let (width, height, rects) = self.export_rects(style);
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" \
+             viewBox=\"0 0 {} {}\" shape-rendering=\"crispEdges\">\n",
+            width, height, width, height
+        );
+        for rect in rects.iter() {
+            svg.push_str(&format!(
+                "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"#{:02x}{:02x}{:02x}\"/>\n",
+                rect.x0, rect.y0, rect.x1 - rect.x0, rect.y1 - rect.y0,
+                rect.colour.0, rect.colour.1, rect.colour.2
+            ));
+        }
+        svg.push_str("</svg>\n");
+        return svg;
+
+    }
+
+    // Render the maze as RGB pixels, row by row. Returns the width,
+    // height and pixels.
+    pub fn to_pixels(
+        &self,
+        style: &ExportStyle
+    ) -> (u32, u32, Vec<u8>) {
+
+        let (width, height, rects) = self.export_rects(style);
+        let mut pixels: Vec<u8> = vec![0; (width * height * 3) as usize];
+        for rect in rects.iter() {
+            for py in rect.y0..rect.y1.min(height) {
+                for px in rect.x0..rect.x1.min(width) {
+                    let i = ((py * width + px) * 3) as usize;
+                    pixels[i] = rect.colour.0;
+                    pixels[i + 1] = rect.colour.1;
+                    pixels[i + 2] = rect.colour.2;
+                }
+            }
+        }
+        return (width, height, pixels);
+
+    }
+
+    // Encode the maze as a PNG image.
+    pub fn write_png<W: Write>(
+        &self,
+        writer: W,
+        style: &ExportStyle
+    ) -> Result<(), Error> {
+
+        let (width, height, pixels) = self.to_pixels(style);
+        let mut encoder = png::Encoder::new(writer, width, height);
+        encoder.set_color(png::ColorType::RGB);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&pixels)?;
+        return Ok(());
+
+    }
+
+    pub fn save_svg<P: AsRef<std::path::Path>>(
+        &self,
+        path: P,
+        style: &ExportStyle
+    ) -> Result<(), Error> {
+
+        let mut file = std::fs::File::create(path)?;
+        file.write_all(self.to_svg(style).as_bytes())?;
+        return Ok(());
+
+    }
+
+    pub fn save_png<P: AsRef<std::path::Path>>(
+        &self,
+        path: P,
+        style: &ExportStyle
+    ) -> Result<(), Error> {
+
+        let file = std::fs::File::create(path)?;
+        return self.write_png(std::io::BufWriter::new(file), style);
+
+    }
+
+}
@@ -0,0 +1,522 @@
+// Maze file formats.
+//
+// Text format: the `draw_text` output. Each cell is two columns and two
+// lines wide; a filled block marks the cell itself and the passages
+// leading +y (right) and +x (down) out of it, blanks are walls. Any
+// non-blank character counts as a block, so '#' can be used when
+// hand-editing. Masked out cells have no block, and the others must all
+// be connected. Crossings of a weave maze are drawn as "||" when the
+// passage along x runs on top and "==" when the one along y does.
+// The maze may be preceded by header lines, in this order when written:
+//   "Seed: N",
+//   "Wrap: x", "Wrap: y" or "Wrap: xy" for wrapping mazes,
+//   "Room: X Y SIZE_X SIZE_Y" for every room.
+//
+// Binary format (little endian):
+//   "GRYD", version (u8, 1), size_x (u32), size_y (u32),
+//   has seed (u8), seed (u64),
+//   wrap (u8, bit 0 for x and bit 1 for y),
+//   room count (u32), then x, y, size_x and size_y (u32 each) of every
+//   room,
+//   one byte per cell, x-major: the `open_dir` bitmask in bits 0 to 3,
+//   bit 4 for a crossing with the passage along x on top, bit 5 for one
+//   along y, and bit 7 for masked out cells.
+
+use std::io::Read;
+use std::io::Write;
+use crate::mazegen::*;
+use crate::mazemask::MazeMask;
+use crate::mazerooms::Room;
+
+const MAGIC: &[u8; 4] = b"GRYD";
+const VERSION: u8 = 1;
+const MASKED: u8 = 0x80;
+const CROSSING: u8 = 0x10;
+const BLOCK: &str = "██";
+const BLANK: &str = "  ";
+const CROSSINGS: [&str; 2] = ["||", "=="];
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    BadMagic,
+    UnsupportedVersion(u8),
+    Truncated,
+    BadSize,
+    InconsistentWalls(usize, usize),
+    DisconnectedMask,
+    BadHeader(String),      // Header line of the text format
+    BadRoom(usize),         // Index of a room not inside the maze
+}
+
+impl From<std::io::Error> for Error {
+
+    fn from(inp: std::io::Error) -> Self {
+        return Error::Io(inp);
+    }
+
+}
+
+// Text files are recognized by their extension, everything else is binary.
+fn is_text_path<P: AsRef<std::path::Path>>(
+    path: P
+) -> bool {
+    return path.as_ref().extension().map_or(false, |e| e == "txt");
+}
+
+fn read_u32(
+    bytes: &[u8],
+    at: usize
+) -> Result<u32, Error> {
+
+    if bytes.len() < at + 4 {
+        return Err(Error::Truncated);
+    }
+    let mut buf = [0u8; 4];
+    buf.copy_from_slice(&bytes[at..at + 4]);
+    return Ok(u32::from_le_bytes(buf));
+
+}
+
+fn read_u64(
+    bytes: &[u8],
+    at: usize
+) -> Result<u64, Error> {
+
+    if bytes.len() < at + 8 {
+        return Err(Error::Truncated);
+    }
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(&bytes[at..at + 8]);
+    return Ok(u64::from_le_bytes(buf));
+
+}
+
+// Mask of a loaded maze. It must leave some cells in the maze, all of
+// them connected, or the maze could not be generated again.
+fn checked_mask(
+    size_x: usize,
+    size_y: usize,
+    active: Vec<bool>
+) -> Result<MazeMask, Error> {
+
+    if !active.iter().any(|a| *a) {
+        return Err(Error::BadSize);
+    }
+    let mask = MazeMask::new(size_x, size_y, active);
+    if !mask.is_connected() {
+        return Err(Error::DisconnectedMask);
+    }
+    return Ok(mask);
+
+}
+
+// Open up the rooms of a loaded maze, which must lie inside it.
+fn add_rooms(
+    maze: &mut Maze2,
+    rooms: Vec<Room>
+) -> Result<(), Error> {
+
+    let size = maze.get_size();
+    for (i, room) in rooms.into_iter().enumerate() {
+        let inside = room.size_x > 0 && room.size_y > 0
+            && room.x.checked_add(room.size_x).map_or(false, |end| end <= size.0)
+            && room.y.checked_add(room.size_y).map_or(false, |end| end <= size.1)
+            && (room.x..room.x + room.size_x)
+                .all(|x| (room.y..room.y + room.size_y).all(|y| maze.is_cell_active(x, y)));
+        if !inside {
+            return Err(Error::BadRoom(i));
+        }
+        maze.add_room(room);
+    }
+    return Ok(());
+
+}
+
+impl Maze2 {
+
+    // Check that every open passage leads between active cells and is
+    // open from the other side too, and that crossings are open all
+    // around.
+    fn check_walls(&self) -> Result<(), Error> {
+
+        let size = self.get_size();
+        for x in 0..size.0 {
+            for y in 0..size.1 {
+                if self.is_crossing(x, y) && self.open_count(x, y) != 4 {
+                    return Err(Error::InconsistentWalls(x, y));
+                }
+                for dir in 0..4 {
+                    if !self.is_cell_open(x, y, dir) {
+                        continue;
+                    }
+                    match self.neighbour(x, y, dir) {
+                        Some((nx, ny)) if self.is_cell_active(x, y)
+                            && self.is_cell_open(nx, ny, opposite_dir(dir)) => {}
+                        _ => return Err(Error::InconsistentWalls(x, y))
+                    }
+                }
+            }
+        }
+        return Ok(());
+
+    }
+
+    // Render the maze in the text format.
+    pub fn to_text(&self) -> String {
+
+        let mut text = String::new();
+        if let Some(seed) = self.get_seed() {
+            text.push_str(&format!("Seed: {}\n", seed));
+        }
+        match self.get_wrap() {
+            (true, true) => text.push_str("Wrap: xy\n"),
+            (true, false) => text.push_str("Wrap: x\n"),
+            (false, true) => text.push_str("Wrap: y\n"),
+            (false, false) => {}
+        }
+        for room in self.borrow_rooms().iter() {
+            text.push_str(&format!("Room: {} {} {} {}\n", room.x, room.y, room.size_x, room.size_y));
+        }
+        let size = self.get_size();
+        for (x, row) in self.borrow_data().chunks(size.1).enumerate() {
+            for (y, cell) in row.iter().enumerate() {
+                text.push_str(match cell.get_crossing() {
+                    Some(over) => CROSSINGS[over as usize],
+                    None if self.is_cell_active(x, y) => BLOCK,
+                    None => BLANK
+                });
+                text.push_str(if cell.is_open(2) { BLOCK } else { BLANK });
+            }
+            text.push('\n');
+            for cell in row.iter() {
+                text.push_str(if cell.is_open(0) { BLOCK } else { BLANK });
+                text.push_str(BLANK);
+            }
+            text.push('\n');
+        }
+        return text;
+
+    }
+
+    // Parse a maze from the text format.
+    pub fn from_text(
+        text: &str
+    ) -> Result<Maze2, Error> {
+
+        let mut lines: Vec<&str> = text.lines().collect();
+        let mut seed: Option<u64> = None;
+        let mut wrap: (bool, bool) = (false, false);
+        let mut rooms: Vec<Room> = Vec::new();
+        while let Some(&first) = lines.first() {
+            let bad = || Error::BadHeader(first.to_string());
+            if first.starts_with("Seed:") {
+                seed = Some(first["Seed:".len()..].trim().parse::<u64>().map_err(|_| bad())?);
+            } else if first.starts_with("Wrap:") {
+                let axes = first["Wrap:".len()..].trim();
+                if axes.is_empty() || axes.chars().any(|c| c != 'x' && c != 'y') {
+                    return Err(bad());
+                }
+                wrap = (axes.contains('x'), axes.contains('y'));
+            } else if first.starts_with("Room:") {
+                let values: Vec<usize> = first["Room:".len()..].split_whitespace()
+                    .map(|v| v.parse::<usize>())
+                    .collect::<Result<Vec<usize>, _>>()
+                    .map_err(|_| bad())?;
+                if values.len() != 4 {
+                    return Err(bad());
+                }
+                rooms.push(Room {
+                    x: values[0],
+                    y: values[1],
+                    size_x: values[2],
+                    size_y: values[3]
+                });
+            } else {
+                break;
+            }
+            lines.remove(0);
+        }
+        while lines.last().map_or(false, |l| l.trim().is_empty()) {
+            lines.pop();
+        }
+
+        // Two characters per unit, kept as the first non-blank one;
+        // editors may have trimmed the trailing blanks of a line.
+        let rows: Vec<Vec<char>> = lines.iter().map(|line| {
+            let chars: Vec<char> = line.chars().collect();
+            return chars.chunks(2)
+                .map(|unit| *unit.iter().find(|c| !c.is_whitespace()).unwrap_or(&' '))
+                .collect();
+        }).collect();
+        let size_x = (rows.len() + 1) / 2;
+        let size_y = (rows.iter().map(|r| r.len()).max().unwrap_or(0) + 1) / 2;
+        if size_x == 0 || size_y == 0 {
+            return Err(Error::BadSize);
+        }
+
+        let unit = |row: usize, col: usize| -> char {
+            return rows.get(row).and_then(|r| r.get(col)).map_or(' ', |c| *c);
+        };
+        let is_block = |row: usize, col: usize| -> bool {
+            return unit(row, col) != ' ';
+        };
+        let mut maze = Maze2::empty(size_x, size_y);
+        let mut active: Vec<bool> = Vec::new();
+        for x in 0..size_x {
+            for y in 0..size_y {
+                active.push(is_block(2 * x, 2 * y));
+            }
+        }
+        if active.iter().any(|a| !*a) {
+            maze = Maze2::masked(checked_mask(size_x, size_y, active)?);
+        }
+        maze.set_wrap(wrap.0, wrap.1);
+        // Passages past the last row or column lead across the seam
+        for x in 0..size_x {
+            for y in 0..size_y {
+                if is_block(2 * x, 2 * y + 1) {
+                    maze.carve_cell(x, y, 2);
+                }
+                if is_block(2 * x + 1, 2 * y) {
+                    maze.carve_cell(x, y, 0);
+                }
+                match unit(2 * x, 2 * y) {
+                    '|' => maze.borrow_cell_mut(x, y).cross(0),
+                    '=' => maze.borrow_cell_mut(x, y).cross(1),
+                    _ => {}
+                }
+            }
+        }
+        add_rooms(&mut maze, rooms)?;
+        maze.check_walls()?;
+        maze.set_generated(seed);
+        return Ok(maze);
+
+    }
+
+    // Encode the maze in the binary format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+
+        let size = self.get_size();
+        let rooms = self.borrow_rooms();
+        let mut bytes: Vec<u8> = Vec::with_capacity(27 + 16 * rooms.len() + size.0 * size.1);
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+        bytes.extend_from_slice(&(size.0 as u32).to_le_bytes());
+        bytes.extend_from_slice(&(size.1 as u32).to_le_bytes());
+        bytes.push(self.get_seed().is_some() as u8);
+        bytes.extend_from_slice(&self.get_seed().unwrap_or(0).to_le_bytes());
+        let wrap = self.get_wrap();
+        bytes.push(wrap.0 as u8 | (wrap.1 as u8) << 1);
+        bytes.extend_from_slice(&(rooms.len() as u32).to_le_bytes());
+        for room in rooms.iter() {
+            for &value in [room.x, room.y, room.size_x, room.size_y].iter() {
+                bytes.extend_from_slice(&(value as u32).to_le_bytes());
+            }
+        }
+        for (x, row) in self.borrow_data().chunks(size.1).enumerate() {
+            for (y, cell) in row.iter().enumerate() {
+                let crossing = cell.get_crossing().map_or(0, |over| CROSSING << over);
+                let masked = if self.is_cell_active(x, y) { 0 } else { MASKED };
+                bytes.push(cell.val() | crossing | masked);
+            }
+        }
+        return bytes;
+
+    }
+
+    // Decode a maze from the binary format.
+    pub fn from_bytes(
+        bytes: &[u8]
+    ) -> Result<Maze2, Error> {
+
+        if bytes.len() < 5 {
+            return Err(Error::Truncated);
+        }
+        if &bytes[0..4] != MAGIC {
+            return Err(Error::BadMagic);
+        }
+        if bytes[4] != VERSION {
+            return Err(Error::UnsupportedVersion(bytes[4]));
+        }
+        let size_x = read_u32(bytes, 5)? as usize;
+        let size_y = read_u32(bytes, 9)? as usize;
+        let has_seed = *bytes.get(13).ok_or(Error::Truncated)? != 0;
+        let seed = read_u64(bytes, 14)?;
+        let wrap = *bytes.get(22).ok_or(Error::Truncated)?;
+        let room_count = read_u32(bytes, 23)? as usize;
+        let mut rooms: Vec<Room> = Vec::new();
+        let mut at: usize = 27;
+        for _ in 0..room_count {
+            let mut values = [0usize; 4];
+            for value in values.iter_mut() {
+                *value = read_u32(bytes, at)? as usize;
+                at += 4;
+            }
+            rooms.push(Room {
+                x: values[0],
+                y: values[1],
+                size_x: values[2],
+                size_y: values[3]
+            });
+        }
+        let cells = &bytes[at..];
+        let count = match size_x.checked_mul(size_y) {
+            Some(count) if count > 0 => count,
+            _ => return Err(Error::BadSize)
+        };
+        if cells.len() < count {
+            return Err(Error::Truncated);
+        }
+
+        let cells = &cells[..count];
+        let mut maze = Maze2::empty(size_x, size_y);
+        if cells.iter().any(|c| c & MASKED != 0) {
+            let active: Vec<bool> = cells.iter().map(|c| c & MASKED == 0).collect();
+            maze = Maze2::masked(checked_mask(size_x, size_y, active)?);
+        }
+        maze.set_wrap(wrap & 1 == 1, wrap >> 1 & 1 == 1);
+        for x in 0..size_x {
+            for y in 0..size_y {
+                let val = cells[x * size_y + y];
+                for dir in 0..4 {
+                    if val >> dir & 1 == 1 {
+                        maze.borrow_cell_mut(x, y).open(dir);
+                    }
+                }
+                for over in 0..2 {
+                    if val & CROSSING << over != 0 {
+                        maze.borrow_cell_mut(x, y).cross(over);
+                    }
+                }
+            }
+        }
+        add_rooms(&mut maze, rooms)?;
+        maze.check_walls()?;
+        maze.set_generated(if has_seed { Some(seed) } else { None });
+        return Ok(maze);
+
+    }
+
+    // Save the maze. Paths ending in ".txt" use the text format,
+    // anything else the binary format.
+    pub fn save<P: AsRef<std::path::Path>>(
+        &self,
+        path: P
+    ) -> Result<(), Error> {
+
+        let mut file = std::fs::File::create(&path)?;
+        if is_text_path(&path) {
+            file.write_all(self.to_text().as_bytes())?;
+        } else {
+            file.write_all(&self.to_bytes())?;
+        }
+        return Ok(());
+
+    }
+
+    // Load a maze saved with `save`.
+    pub fn load<P: AsRef<std::path::Path>>(
+        path: P
+    ) -> Result<Maze2, Error> {
+
+        let mut file = std::fs::File::open(&path)?;
+        if is_text_path(&path) {
+            let mut text = String::new();
+            file.read_to_string(&mut text)?;
+            return Maze2::from_text(&text);
+        }
+        let mut bytes: Vec<u8> = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        return Maze2::from_bytes(&bytes);
+
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::mazerooms::Rooms;
+    use crate::mazeweave::Woven;
+
+    // Masked, wrapping along x, woven and with rooms, so every part of
+    // both formats is used.
+    fn full_maze() -> Maze2 {
+
+        let mask = MazeMask::from_text(
+            "############\n############\n#####..#####\n#####..#####\n############\n############\n############\n"
+        ).unwrap();
+        let mut maze = Maze2::masked(mask);
+        maze.set_wrap(true, false);
+        let generator = Woven {
+            inner: Rooms { inner: Prim, count: 2, min_size: 2, max_size: 3 },
+            density: 0.5
+        };
+        maze.generate_using(&generator, 5);
+        return maze;
+
+    }
+
+    fn assert_same(
+        a: &Maze2,
+        b: &Maze2
+    ) {
+
+        assert_eq!(a.get_size(), b.get_size());
+        assert_eq!(a.get_seed(), b.get_seed());
+        assert_eq!(a.get_wrap(), b.get_wrap());
+        assert_eq!(a.borrow_rooms(), b.borrow_rooms());
+        let size = a.get_size();
+        for x in 0..size.0 {
+            for y in 0..size.1 {
+                assert_eq!(a.is_cell_active(x, y), b.is_cell_active(x, y));
+                assert_eq!(a.get_crossing(x, y), b.get_crossing(x, y));
+                assert_eq!(a.get_cell_val(x, y), b.get_cell_val(x, y));
+            }
+        }
+
+    }
+
+    #[test]
+    fn text_and_binary_formats_round_trip() {
+
+        let maze = full_maze();
+        let size = maze.get_size();
+        assert!(maze.borrow_rooms().len() > 0);
+        assert!((0..size.0).any(|x| (0..size.1).any(|y| maze.is_crossing(x, y))));
+        assert!((0..size.1).any(|y| maze.is_cell_open(0, y, Direction::NegX.as_u8())));
+
+        assert_same(&maze, &Maze2::from_text(&maze.to_text()).unwrap());
+        assert_same(&maze, &Maze2::from_bytes(&maze.to_bytes()).unwrap());
+
+    }
+
+    #[test]
+    fn malformed_headers_are_refused() {
+
+        let body = "██████\n\n";
+        for &header in ["Seed: 12x", "Seed:", "Wrap: z", "Room: 0 0 1", "Room: 0 0 a 1"].iter() {
+            match Maze2::from_text(&format!("{}\n{}", header, body)) {
+                Err(Error::BadHeader(line)) => assert_eq!(line, header),
+                other => panic!("{:?} for {}", other.map(|m| m.to_text()), header)
+            }
+        }
+        match Maze2::from_text(&format!("Room: 1 0 3 1\n{}", body)) {
+            Err(Error::BadRoom(0)) => {}
+            other => panic!("{:?}", other.map(|m| m.to_text()))
+        }
+        assert_eq!(Maze2::from_text(&format!("Seed: 12\n{}", body)).unwrap().get_seed(), Some(12));
+
+        let mut bytes = full_maze().to_bytes();
+        bytes[4] = VERSION + 1;
+        match Maze2::from_bytes(&bytes) {
+            Err(Error::UnsupportedVersion(version)) => assert_eq!(version, VERSION + 1),
+            other => panic!("{:?}", other.map(|m| m.to_text()))
+        }
+
+    }
+
+}
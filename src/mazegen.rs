@@ -15,65 +15,556 @@
 //
 
 extern crate rand;
+extern crate rand_chacha;
 
 use rand::Rng;
+use rand::RngCore;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use std::borrow::BorrowMut;
+use std::collections::VecDeque;
+use crate::mazemask::MazeMask;
+use crate::mazerooms::Room;
 
-pub struct MazeCell2 {
-    open_dir: u8,
-    visited: bool,
-    listed: bool,
-}
+// One cell packed into a byte: bits 0 to 5 are the open passages by
+// direction, bit 6 marks a crossing and bit 7 tells its passage along y
+// is on top.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MazeCell2(u8);
+
+const OPEN_DIRS: u8 = 0x3F;
+const CROSSING: u8 = 0x40;
+const CROSSING_OVER_Y: u8 = 0x80;
 
 impl MazeCell2 {
     
     pub fn new() -> MazeCell2 {
-        
-        return MazeCell2 {
-            open_dir: 0,
-            visited: false,
-            listed: false,
-        };
-
+        return MazeCell2(0);
     }
 
-    pub fn visit(&mut self) {
-        self.visited = true;
-    } 
-
-    pub fn list(&mut self) {
-        self.listed = true;
-    }
-
-    pub fn unlist(&mut self) {
-        self.listed = false;
+    pub fn open(&mut self, dir: u8) {
+        self.0 |= 1 << dir;
     }
 
-    pub fn open(&mut self, dir: u8) {
-        self.open_dir |= 1 << dir;
+    pub fn close(&mut self, dir: u8) {
+        self.0 &= !(1 << dir);
     }
 
-    pub fn is_visited(&self) -> bool {
-        return self.visited;
+    // Make the cell a crossing of two straight passages, one over the
+    // other. `over` is the axis of the passage on top: 0 for x, 1 for y.
+    pub fn cross(&mut self, over: u8) {
+        self.0 |= CROSSING;
+        if over == 1 {
+            self.0 |= CROSSING_OVER_Y;
+        } else {
+            self.0 &= !CROSSING_OVER_Y;
+        }
     }
 
-    pub fn is_listed(&self) -> bool {
-        return self.listed;
+    pub fn get_crossing(&self) -> Option<u8> {
+        if self.0 & CROSSING == 0 {
+            return None;
+        }
+        return Some(self.0 >> 7);
     }
 
     pub fn val(&self) -> u8 {
-        return self.open_dir;
+        return self.0 & OPEN_DIRS;
     }
 
     pub fn is_open(&self, dir:u8) -> bool {
-        return self.open_dir >> dir & 1 == 1;
+        return self.0 >> dir & 1 == 1;
     }
 
 }
 
+// Cells are stored x-major in a single buffer, one byte each.
+#[derive(Clone)]
 pub struct Maze2 {
     generated: bool,
+    seed: Option<u64>,
     size: (usize, usize),
-    data: Vec<Vec<MazeCell2>>
+    data: Vec<MazeCell2>,
+    mask: Option<MazeMask>,
+    wrap: (bool, bool),
+    rooms: Vec<Room>
+}
+
+// Direction pointing back from the neighbour in direction `dir`.
+pub fn opposite_dir(dir: u8) -> u8 {
+    return dir ^ 1;
+}
+
+// Direction out of a cell. The value is the `dir` bit of the cell, so
+// directions along x come first and each is followed by its opposite.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    PosX = 0,
+    NegX = 1,
+    PosY = 2,
+    NegY = 3
+}
+
+impl Direction {
+
+    pub const ALL: [Direction; 4] = [
+        Direction::PosX,
+        Direction::NegX,
+        Direction::PosY,
+        Direction::NegY
+    ];
+
+    pub fn from_u8(dir: u8) -> Option<Direction> {
+        return Direction::ALL.get(dir as usize).copied();
+    }
+
+    pub fn as_u8(self) -> u8 {
+        return self as u8;
+    }
+
+    pub fn opposite(self) -> Direction {
+        return Direction::ALL[opposite_dir(self.as_u8()) as usize];
+    }
+
+    // 0 for directions along x, 1 along y.
+    pub fn axis(self) -> u8 {
+        return self.as_u8() >> 1;
+    }
+
+    // Change of the cell coordinates when moving one cell this way.
+    pub fn offset(self) -> (isize, isize) {
+        return match self {
+            Direction::PosX => (1, 0),
+            Direction::NegX => (-1, 0),
+            Direction::PosY => (0, 1),
+            Direction::NegY => (0, -1)
+        };
+    }
+
+}
+
+// Why a cell could not be looked at or changed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CellError {
+    OutOfBounds(usize, usize),
+    MaskedOut(usize, usize),
+    NoNeighbour(usize, usize, Direction)     // At the edge, or masked out
+}
+
+// Pick a random index in 0..len. Sampled as u32 so that a given seed
+// yields the same sequence on both 32-bit and 64-bit targets. Scales a
+// random u32 up to the range and rejects the few values that would make
+// some indices more likely (Lemire's method). Unlike `gen_range` this
+// only divides when a value lands in the biased zone, which matters as
+// generation picks an index for nearly every cell.
+pub fn rand_index<R: Rng + ?Sized>(
+    rng: &mut R,
+    len: usize
+) -> usize {
+
+    let range = len as u32;
+    let mut m = rng.next_u32() as u64 * range as u64;
+    if (m as u32) < range {
+        let threshold = range.wrapping_neg() % range;
+        while (m as u32) < threshold {
+            m = rng.next_u32() as u64 * range as u64;
+        }
+    }
+    return (m >> 32) as usize;
+
+}
+
+// Shuffle a slice in place using `rand_index`, so the order is the same
+// on every platform.
+pub fn shuffle<T, R: Rng + ?Sized>(
+    rng: &mut R,
+    items: &mut [T]
+) {
+    for i in (1..items.len()).rev() {
+        let j = rand_index(rng, i + 1);
+        items.swap(i, j);
+    }
+}
+
+// Most cells a cell can be adjacent to, on a hexagonal grid or at the
+// center of a polar one.
+pub const MAX_ADJACENT: usize = 6;
+
+// Adjacent cells of a cell as (slot, cell), see `MazeGraph::adjacent`.
+// Kept in place rather than in a `Vec` as generators ask for them at
+// nearly every step. Derefs to a slice of the cells.
+#[derive(Debug, Clone, Copy)]
+pub struct Adjacent {
+    cells: [(u8, usize); MAX_ADJACENT],
+    len: usize
+}
+
+impl Adjacent {
+
+    pub fn new() -> Adjacent {
+        return Adjacent {
+            cells: [(0, 0); MAX_ADJACENT],
+            len: 0
+        };
+    }
+
+    pub fn push(
+        &mut self,
+        slot: u8,
+        cell: usize
+    ) {
+        self.cells[self.len] = (slot, cell);
+        self.len += 1;
+    }
+
+    // Add a cell only if `keep`. Does not branch on it, which is much
+    // faster when filtering on something as random as visited cells.
+    pub fn push_if(
+        &mut self,
+        slot: u8,
+        cell: usize,
+        keep: bool
+    ) {
+        self.cells[self.len] = (slot, cell);
+        self.len += keep as usize;
+    }
+
+}
+
+impl std::ops::Deref for Adjacent {
+
+    type Target = [(u8, usize)];
+
+    fn deref(&self) -> &[(u8, usize)] {
+        return &self.cells[..self.len];
+    }
+
+}
+
+// A maze seen as a graph: cells are numbered 0..cell_count, and each
+// cell reaches its adjacent cells through numbered slots. Lets the
+// generation algorithms work on any grid topology.
+pub trait MazeGraph {
+
+    fn cell_count(&self) -> usize;
+
+    // Adjacent cells of a cell.
+    fn adjacent(
+        &self,
+        cell: usize
+    ) -> Adjacent;
+
+    fn is_linked(
+        &self,
+        cell: usize,
+        slot: u8
+    ) -> bool;
+
+    // Open the passage through a slot, on both sides.
+    fn link(
+        &mut self,
+        cell: usize,
+        slot: u8
+    );
+
+}
+
+// An algorithm that carves passages into an empty maze.
+pub trait MazeGenerator {
+
+    fn generate(
+        &self,
+        maze: &mut Maze2,
+        rng: &mut dyn RngCore
+    );
+
+}
+
+impl<G: MazeGenerator + ?Sized> MazeGenerator for Box<G> {
+
+    fn generate(
+        &self,
+        maze: &mut Maze2,
+        rng: &mut dyn RngCore
+    ) {
+        (**self).generate(maze, rng);
+    }
+
+}
+
+// Randomized Prim's algorithm. Produces short, branchy corridors.
+pub struct Prim;
+
+impl MazeGenerator for Prim {
+
+    fn generate(
+        &self,
+        maze: &mut Maze2,
+        rng: &mut dyn RngCore
+    ) {
+        // Straight through, without recording the events
+        let mut steps = PrimSteps::new(maze, rng);
+        steps.record = false;
+        steps.start();
+        while steps.listed > 0 {
+            steps.step();
+        }
+    }
+
+}
+
+// One change made while generating a maze.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GenEvent {
+    Visit(usize, usize),        // Cell became part of the maze
+    Carve(usize, usize, u8),    // Passage opened from a cell towards `dir`
+    List(usize, usize),         // Cell added to the frontier
+    Unlist(usize, usize),       // Cell removed from the frontier
+}
+
+const VISITED: u8 = 1;
+const LISTED: u8 = 2;
+
+// Randomized Prim's algorithm, run step by step as an iterator of
+// events. Between steps the frontier and the visited and listed flags of
+// the cells show the state of the generator. The flags are kept here,
+// apart from the maze, two bits per cell, and dropped along with the
+// steps. The maze is either borrowed or owned, so the steps can be kept
+// between frames while the maze grows.
+pub struct PrimSteps<M: BorrowMut<Maze2>, R: RngCore> {
+    maze: M,
+    rng: R,
+    started: bool,
+    record: bool,
+    flags: Vec<u64>,
+    listed: usize,                          // Length of the frontier
+    to_be_generated: Vec<(usize, usize)>,   // Frontier, then spare room
+    events: VecDeque<GenEvent>
+}
+
+impl<M: BorrowMut<Maze2>, R: RngCore> PrimSteps<M, R> {
+
+    pub fn new(
+        maze: M,
+        rng: R
+    ) -> PrimSteps<M, R> {
+
+        let size = maze.borrow().size;
+        let cells = size.0 * size.1;
+        return PrimSteps {
+            maze: maze,
+            rng: rng,
+            started: false,
+            record: true,
+            flags: vec![0; (cells + 31) / 32],
+            listed: 0,
+            to_be_generated: Vec::new(),
+            events: VecDeque::new()
+        };
+
+    }
+
+    pub fn borrow_maze(&self) -> &Maze2 {
+        return self.maze.borrow();
+    }
+
+    // Give the maze back, e.g. once it is done.
+    pub fn into_maze(self) -> M {
+        return self.maze;
+    }
+
+    // Cells next to the maze, waiting to be joined.
+    pub fn frontier(&self) -> &[(usize, usize)] {
+        return &self.to_be_generated[..self.listed];
+    }
+
+    fn flag(
+        &self,
+        cell: usize
+    ) -> u8 {
+        return (self.flags[cell / 32] >> (cell % 32 * 2)) as u8 & 3;
+    }
+
+    fn set_flag(
+        &mut self,
+        cell: usize,
+        flag: u8,
+        on: bool
+    ) {
+        let bits = (flag as u64) << (cell % 32 * 2);
+        if on {
+            self.flags[cell / 32] |= bits;
+        } else {
+            self.flags[cell / 32] &= !bits;
+        }
+    }
+
+    pub fn is_visited(
+        &self,
+        x: usize,
+        y: usize
+    ) -> bool {
+        return self.flag(self.borrow_maze().index(x, y)) & VISITED != 0;
+    }
+
+    pub fn is_listed(
+        &self,
+        x: usize,
+        y: usize
+    ) -> bool {
+        return self.flag(self.borrow_maze().index(x, y)) & LISTED != 0;
+    }
+
+    pub fn is_done(&self) -> bool {
+        return self.started && self.listed == 0 && self.events.len() == 0;
+    }
+
+    fn event(
+        &mut self,
+        event: GenEvent
+    ) {
+        if self.record {
+            self.events.push_back(event);
+        }
+    }
+
+    fn visit(
+        &mut self,
+        pos: (usize, usize)
+    ) {
+        let i = self.borrow_maze().index(pos.0, pos.1);
+        self.set_flag(i, VISITED, true);
+        self.event(GenEvent::Visit(pos.0, pos.1));
+    }
+
+    // Add a cell to the frontier if it is new to it, i.e. neither visited
+    // nor listed already. Which it is is random, so nothing branches on
+    // it: the cell is written past the end of the frontier either way
+    // and only counted in if it is new. See `listed_since` for the
+    // events.
+    fn list(
+        &mut self,
+        pos: (usize, usize),
+        i: usize,
+        fresh: bool
+    ) {
+        if self.listed == self.to_be_generated.len() {
+            self.to_be_generated.resize(2 * self.listed + 4, (0, 0));
+        }
+        self.to_be_generated[self.listed] = pos;
+        self.listed += fresh as usize;
+        self.flags[i / 32] |= (fresh as u64 * LISTED as u64) << (i % 32 * 2);
+    }
+
+    // Events for the cells listed since the frontier had `from` cells.
+    fn listed_since(
+        &mut self,
+        from: usize
+    ) {
+        if self.record {
+            for k in from..self.listed {
+                let pos = self.to_be_generated[k];
+                self.events.push_back(GenEvent::List(pos.0, pos.1));
+            }
+        }
+    }
+
+    fn start(&mut self) {
+
+        // Nothing to generate in a maze without cells
+        self.started = true;
+        if self.borrow_maze().cell_count() == 0 {
+            return;
+        }
+
+        // Starting cell
+        let maze = self.maze.borrow();
+        let rng = &mut self.rng;
+        let current_pos: (usize, usize) = match maze.mask {
+            Some(ref mask) => {
+                let cells = mask.active_cells();
+                cells[rand_index(rng, cells.len())]
+            }
+            None => (
+                rand_index(rng, maze.size.0), 
+                rand_index(rng, maze.size.1)
+            )
+        };
+        self.visit(current_pos);
+
+        // List all adjacent cells
+        for &dir in [1, 0, 3, 2].iter() {
+            if let Some(pos) = self.borrow_maze().neighbour(current_pos.0, current_pos.1, dir) {
+                let i = self.borrow_maze().index(pos.0, pos.1);
+                self.list(pos, i, self.flag(i) == 0);
+            }
+        }
+        self.listed_since(0);
+
+    }
+
+    fn step(&mut self) {
+
+        // Select random cell from to be generated list
+        let rand_cell = rand_index(&mut self.rng, self.listed);
+        let current_pos = self.to_be_generated[rand_cell];
+        let current = self.borrow_maze().index(current_pos.0, current_pos.1);
+
+        // List the neighbours that are new to the frontier, and count the
+        // visited ones, which passages can be opened to
+        let listed = self.listed;
+        let mut rand_dirs: [(u8, usize); 4] = [(0, 0); 4];
+        let mut dir_count: usize = 0;
+        for dir in 0..4 {
+            if let Some((nx, ny)) = self.borrow_maze().neighbour(current_pos.0, current_pos.1, dir) {
+                let next = self.borrow_maze().index(nx, ny);
+                let flag = self.flag(next);
+                rand_dirs[dir_count] = (dir, next);
+                dir_count += (flag & VISITED) as usize;
+                self.list((nx, ny), next, flag == 0);
+            }
+        }
+        self.listed_since(listed);
+
+        // Select random direction and open passage. Both cells are part
+        // of the maze, so the checks of `carve_cell` can be skipped
+        let (rand_dir, next) = rand_dirs[rand_index(&mut self.rng, dir_count)];
+        let maze = self.maze.borrow_mut();
+        maze.data[current].open(rand_dir);
+        maze.data[next].open(opposite_dir(rand_dir));
+        self.event(GenEvent::Carve(current_pos.0, current_pos.1, rand_dir));
+
+        // Mark cell as visited
+        self.visit(current_pos);
+        // Unlist cell
+        self.set_flag(current, LISTED, false);
+        self.listed -= 1;
+        self.to_be_generated[rand_cell] = self.to_be_generated[self.listed];
+        self.event(GenEvent::Unlist(current_pos.0, current_pos.1));
+
+    }
+
+}
+
+impl<M: BorrowMut<Maze2>, R: RngCore> Iterator for PrimSteps<M, R> {
+
+    type Item = GenEvent;
+
+    // Runs one more step of the algorithm whenever the events of the
+    // last one have all been taken.
+    fn next(&mut self) -> Option<GenEvent> {
+
+        if self.events.len() == 0 {
+            if !self.started {
+                self.start();
+            } else if self.listed > 0 {
+                self.step();
+            }
+        }
+        return self.events.pop_front();
+
+    }
+
 }
 
 impl Maze2 {
@@ -83,27 +574,121 @@ impl Maze2 {
         size_y: usize
     ) -> Maze2 {
     
-        let mut maze = Maze2 {
+        let maze = Maze2 {
             generated: false,
+            seed: None,
             size: (size_x, size_y),
-            data: Vec::new()
+            data: vec![MazeCell2::new(); size_x * size_y],
+            mask: None,
+            wrap: (false, false),
+            rooms: Vec::new()
         };
-        for i in 0..size_x {
-            maze.data.push(Vec::new());
-            for _ in 0..size_y {
-                maze.data[i].push(MazeCell2::new());
-            }
-        }
         return maze;
 
     }
 
+    // Empty maze shaped by a mask. Masked out cells are never carved and
+    // stay walled off. Generating needs a connected mask, see
+    // `MazeMask::largest_region`.
+    pub fn masked(
+        mask: MazeMask
+    ) -> Maze2 {
+
+        let size = mask.get_size();
+        let mut maze = Maze2::empty(size.0, size.1);
+        maze.mask = Some(mask);
+        return maze;
+
+    }
+
+    // Wall up every cell again, keeping the size, mask and wrapping, so
+    // the maze can be generated anew.
+    pub fn reset(&mut self) {
+
+        for cell in self.data.iter_mut() {
+            *cell = MazeCell2::new();
+        }
+        self.rooms.clear();
+        self.seed = None;
+        self.generated = false;
+
+    }
+
+    // Reset the maze to a new size. The mask no longer fits and is
+    // dropped; wrapping is kept.
+    pub fn resize(
+        &mut self,
+        size_x: usize,
+        size_y: usize
+    ) {
+
+        let wrap = self.wrap;
+        *self = Maze2::empty(size_x, size_y);
+        self.set_wrap(wrap.0, wrap.1);
+
+    }
+
+    // Let passages leave the maze on one edge and come back in on the
+    // opposite one: along x, y or both, for a cylinder or a torus. Only
+    // axes at least 3 cells long wrap.
+    pub fn set_wrap(
+        &mut self,
+        wrap_x: bool,
+        wrap_y: bool
+    ) {
+
+        if self.generated {
+            panic!("Maze already generated!")
+        }
+        self.wrap = (wrap_x && self.size.0 > 2, wrap_y && self.size.1 > 2);
+
+    }
+
+    pub fn get_wrap(&self) -> (bool, bool) {
+        return self.wrap;
+    }
+
+    pub fn borrow_mask(&self) -> Option<&MazeMask> {
+        return self.mask.as_ref();
+    }
+
+    // Whether a cell is part of the maze, i.e. not masked out.
+    pub fn is_cell_active(
+        &self,
+        x: usize,
+        y: usize
+    ) -> bool {
+        return self.mask.as_ref().map_or(true, |m| m.is_active(x, y));
+    }
+
+    // Position of the first cell that is part of the maze.
+    pub fn first_cell(&self) -> (usize, usize) {
+        return self.mask.as_ref().map_or((0, 0), |m| m.active_cells()[0]);
+    }
+
+    // Place of a cell in the cell buffer, x-major.
+    pub fn index(
+        &self,
+        x: usize,
+        y: usize
+    ) -> usize {
+        return x * self.size.1 + y;
+    }
+
+    // Cell at a place in the cell buffer.
+    pub fn position(
+        &self,
+        index: usize
+    ) -> (usize, usize) {
+        return (index / self.size.1, index % self.size.1);
+    }
+
     pub fn get_cell_val(
         &self,
         x: usize,
         y: usize
     ) -> u8 {
-        return self.data[x][y].val();
+        return self.data[self.index(x, y)].val();
     }
 
     pub fn is_cell_open(
@@ -112,148 +697,731 @@ impl Maze2 {
         y: usize,
         dir: u8
     ) -> bool {
-        return self.data[x][y].is_open(dir);
+        return self.data[self.index(x, y)].is_open(dir);
     }
 
     pub fn get_size(&self) -> (usize, usize) {
         return self.size;
     }
 
-    pub fn borrow_data(&self) -> &Vec<Vec<MazeCell2>> {
+    // All cells, x-major.
+    pub fn borrow_data(&self) -> &[MazeCell2] {
         return &self.data;
     }
 
+    pub fn borrow_cell(
+        &self,
+        x: usize,
+        y: usize
+    ) -> &MazeCell2 {
+        return &self.data[self.index(x, y)];
+    }
+
+    pub fn borrow_cell_mut(
+        &mut self,
+        x: usize,
+        y: usize
+    ) -> &mut MazeCell2 {
+        let i = self.index(x, y);
+        return &mut self.data[i];
+    }
+
+    // Position of the adjacent cell in direction `dir`, if it lies
+    // inside the maze and is not masked out. Wraps around the edges of
+    // wrapping axes.
+    pub fn neighbour(
+        &self,
+        x: usize,
+        y: usize,
+        dir: u8
+    ) -> Option<(usize, usize)> {
+        let pos = match dir {
+            0 if x + 1 < self.size.0 => Some((x + 1, y)),
+            0 if self.wrap.0 => Some((0, y)),
+            1 if x > 0 => Some((x - 1, y)),
+            1 if self.wrap.0 => Some((self.size.0 - 1, y)),
+            2 if y + 1 < self.size.1 => Some((x, y + 1)),
+            2 if self.wrap.1 => Some((x, 0)),
+            3 if y > 0 => Some((x, y - 1)),
+            3 if self.wrap.1 => Some((x, self.size.1 - 1)),
+            _ => None
+        };
+        return pos.filter(|&(nx, ny)| self.is_cell_active(nx, ny));
+    }
+
+    // Axis of the passage on top if the cell is a crossing, see
+    // `MazeCell2::cross`.
+    pub fn get_crossing(
+        &self,
+        x: usize,
+        y: usize
+    ) -> Option<u8> {
+        return self.data[self.index(x, y)].get_crossing();
+    }
+
+    pub fn is_crossing(
+        &self,
+        x: usize,
+        y: usize
+    ) -> bool {
+        return self.get_crossing(x, y).is_some();
+    }
+
+    // Turn a cell into a crossing: passages along x and along y run
+    // straight through it, the one along axis `over` on top.
+    pub fn cross(
+        &mut self,
+        x: usize,
+        y: usize,
+        over: u8
+    ) {
+        for dir in 0..4 {
+            self.carve_cell(x, y, dir);
+        }
+        self.borrow_cell_mut(x, y).cross(over);
+    }
+
+    // Directions a cell can be left through after entering it through
+    // `entered`, the direction of travel. Crossings are only left
+    // straight ahead or back.
+    pub fn exits(
+        &self,
+        x: usize,
+        y: usize,
+        entered: Option<u8>
+    ) -> Vec<u8> {
+
+        let mut dirs: Vec<u8> = Vec::new();
+        for dir in 0..4 {
+            if !self.is_cell_open(x, y, dir) {
+                continue;
+            }
+            match entered {
+                Some(from) if self.is_crossing(x, y) && dir >> 1 != from >> 1 => {}
+                _ => dirs.push(dir)
+            }
+        }
+        return dirs;
+
+    }
+
+    // Direction leading from a cell to an adjacent one.
+    pub fn dir_towards(
+        &self,
+        from: (usize, usize),
+        to: (usize, usize)
+    ) -> Option<u8> {
+        return (0..4).find(|&dir| self.neighbour(from.0, from.1, dir) == Some(to));
+    }
+
+    // Open the passage between a cell and its neighbour in direction
+    // `dir`, on both sides. Masked out cells are left closed.
+    pub fn carve_cell(
+        &mut self,
+        x: usize,
+        y: usize,
+        dir: u8
+    ) {
+        if !self.is_cell_active(x, y) {
+            return;
+        }
+        if let Some((nx, ny)) = self.neighbour(x, y, dir) {
+            self.borrow_cell_mut(x, y).open(dir);
+            self.borrow_cell_mut(nx, ny).open(opposite_dir(dir));
+        }
+    }
+
+    // Close the passage between a cell and its neighbour in direction
+    // `dir`, on both sides.
+    pub fn wall_cell(
+        &mut self,
+        x: usize,
+        y: usize,
+        dir: u8
+    ) {
+        self.borrow_cell_mut(x, y).close(dir);
+        if let Some((nx, ny)) = self.neighbour(x, y, dir) {
+            self.borrow_cell_mut(nx, ny).close(opposite_dir(dir));
+        }
+    }
+
+    // Whether a position lies inside the maze and is not masked out.
+    pub fn has_cell(
+        &self,
+        cell: (usize, usize)
+    ) -> bool {
+        return self.check_cell(cell).is_ok();
+    }
+
+    fn check_bounds(
+        &self,
+        cell: (usize, usize)
+    ) -> Result<(), CellError> {
+
+        if cell.0 >= self.size.0 || cell.1 >= self.size.1 {
+            return Err(CellError::OutOfBounds(cell.0, cell.1));
+        }
+        return Ok(());
+
+    }
+
+    fn check_cell(
+        &self,
+        cell: (usize, usize)
+    ) -> Result<(), CellError> {
+
+        self.check_bounds(cell)?;
+        if !self.is_cell_active(cell.0, cell.1) {
+            return Err(CellError::MaskedOut(cell.0, cell.1));
+        }
+        return Ok(());
+
+    }
+
+    // Cell at a position that may lie outside the maze.
+    pub fn get_cell(
+        &self,
+        cell: (usize, usize)
+    ) -> Option<&MazeCell2> {
+        return self.check_bounds(cell).ok().map(|_| self.borrow_cell(cell.0, cell.1));
+    }
+
+    // Whether a cell is open in a direction. Unlike `is_cell_open` this
+    // fails on positions outside the maze instead of panicking.
+    pub fn is_open(
+        &self,
+        cell: (usize, usize),
+        dir: Direction
+    ) -> Result<bool, CellError> {
+
+        self.check_bounds(cell)?;
+        return Ok(self.is_cell_open(cell.0, cell.1, dir.as_u8()));
+
+    }
+
+    // Adjacent cell in a direction, see `neighbour`. None for positions
+    // outside the maze.
+    pub fn next_cell(
+        &self,
+        cell: (usize, usize),
+        dir: Direction
+    ) -> Option<(usize, usize)> {
+
+        if self.check_bounds(cell).is_err() {
+            return None;
+        }
+        return self.neighbour(cell.0, cell.1, dir.as_u8());
+
+    }
+
+    // Adjacent cells of a cell with the direction leading to them,
+    // whether or not the passage is open.
+    pub fn neighbours<'a>(
+        &'a self,
+        cell: (usize, usize)
+    ) -> impl Iterator<Item = (Direction, (usize, usize))> + 'a {
+        return Direction::ALL.iter()
+            .filter_map(move |&dir| self.next_cell(cell, dir).map(|next| (dir, next)));
+    }
+
+    // Adjacent cells reached through open passages. Crossings lead on
+    // in all four directions, see `exits` for the way through them.
+    pub fn passages<'a>(
+        &'a self,
+        cell: (usize, usize)
+    ) -> impl Iterator<Item = (Direction, (usize, usize))> + 'a {
+        return self.neighbours(cell)
+            .filter(move |&(dir, _)| self.is_cell_open(cell.0, cell.1, dir.as_u8()));
+    }
+
+    // Open the passage from a cell in a direction, on both sides. Fails
+    // if either cell is outside the maze or masked out.
+    pub fn carve(
+        &mut self,
+        cell: (usize, usize),
+        dir: Direction
+    ) -> Result<(), CellError> {
+
+        self.check_cell(cell)?;
+        if self.next_cell(cell, dir).is_none() {
+            return Err(CellError::NoNeighbour(cell.0, cell.1, dir));
+        }
+        self.carve_cell(cell.0, cell.1, dir.as_u8());
+        return Ok(());
+
+    }
+
+    // Close the passage from a cell in a direction, on both sides.
+    // Walling a cell at the edge of the maze is fine.
+    pub fn wall(
+        &mut self,
+        cell: (usize, usize),
+        dir: Direction
+    ) -> Result<(), CellError> {
+
+        self.check_bounds(cell)?;
+        self.wall_cell(cell.0, cell.1, dir.as_u8());
+        return Ok(());
+
+    }
+
+    // Open up a room: all walls between its cells are removed.
+    pub fn add_room(
+        &mut self,
+        room: Room
+    ) {
+
+        for x in room.x..room.x + room.size_x {
+            for y in room.y..room.y + room.size_y {
+                if x + 1 < room.x + room.size_x {
+                    self.carve_cell(x, y, 0);
+                }
+                if y + 1 < room.y + room.size_y {
+                    self.carve_cell(x, y, 2);
+                }
+            }
+        }
+        self.rooms.push(room);
+
+    }
+
+    pub fn borrow_rooms(&self) -> &Vec<Room> {
+        return &self.rooms;
+    }
+
+    // Index of the room a cell is part of.
+    pub fn room_at(
+        &self,
+        x: usize,
+        y: usize
+    ) -> Option<usize> {
+        return self.rooms.iter().position(|r| r.contains(x, y));
+    }
+
+    // Seed the maze was generated from, if it was generated from one.
+    pub fn get_seed(&self) -> Option<u64> {
+        return self.seed;
+    }
+
+    // Generate maze from a fresh random seed.
     pub fn generate(
         &mut self 
     ) {
 
+        let seed: u64 = rand::thread_rng().gen();
+        self.generate_with_seed(seed);
+
+    }
+
+    // Generate maze from a seed. The same seed and size always produce
+    // the same maze.
+    pub fn generate_with_seed(
+        &mut self,
+        seed: u64
+    ) {
+        self.generate_using(&Prim, seed);
+    }
+
+    // Generate maze using the given random number generator.
+    pub fn generate_with_rng<R: RngCore>(
+        &mut self,
+        rng: &mut R
+    ) {
+        self.generate_using_rng(&Prim, rng);
+    }
+
+    // Generate maze from a seed with the given algorithm.
+    pub fn generate_using<G: MazeGenerator + ?Sized>(
+        &mut self,
+        generator: &G,
+        seed: u64
+    ) {
+
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        self.generate_using_rng(generator, &mut rng);
+        self.seed = Some(seed);
+
+    }
+
+    // Throw the current maze away and generate a new one from a seed
+    // with the given algorithm.
+    pub fn regenerate_using<G: MazeGenerator + ?Sized>(
+        &mut self,
+        generator: &G,
+        seed: u64
+    ) {
+
+        self.reset();
+        self.generate_using(generator, seed);
+
+    }
+
+    // Generate maze with the given algorithm and random number generator.
+    // A maze can only be generated once, see `reset`.
+    pub fn generate_using_rng<G: MazeGenerator + ?Sized, R: RngCore>(
+        &mut self,
+        generator: &G,
+        rng: &mut R
+    ) {
+
         if self.generated {
             panic!("Maze already generated!")
         }
+        if let Some(ref mask) = self.mask {
+            if !mask.is_connected() {
+                panic!("Mask is not connected!")
+            }
+        }
+        generator.generate(self, rng);
+        if self.mask.is_some() {
+            self.connect_parts(rng);
+        }
+        self.generated = true;
 
-        // Initialize generator
-        let mut rng = rand::thread_rng();
-        let mut to_be_generated: Vec<(usize, usize)> = Vec::new();
-        
-        // Starting cell
-        let mut current_pos: (usize, usize) = (
-            rng.gen_range(0, self.size.0), 
-            rng.gen_range(0, self.size.1)
-        );
-        self.data[current_pos.0][current_pos.1].visit();
+    }
 
-        // List all adjacent cells
-        if current_pos.0 > 0 {
-            to_be_generated.push((current_pos.0 - 1, current_pos.1));
-            self.data[current_pos.0 - 1][current_pos.1].list();
-        }
-        if current_pos.0 < self.size.0 - 1 {
-            to_be_generated.push((current_pos.0 + 1, current_pos.1));
-            self.data[current_pos.0 + 1][current_pos.1].list();
-        }
-        if current_pos.1 > 0 {
-            to_be_generated.push((current_pos.0, current_pos.1 - 1));
-            self.data[current_pos.0][current_pos.1 - 1].list();
-        }
-        if current_pos.1 < self.size.1 - 1 {
-            to_be_generated.push((current_pos.0, current_pos.1 + 1));
-            self.data[current_pos.0][current_pos.1 + 1].list();
-        }
-
-        // Generate cells randomly
-        while to_be_generated.len() > 0 {
-            // Select random cell from to be generated list
-            let rand_cell = rng.gen_range(0, to_be_generated.len());
-            current_pos = to_be_generated[rand_cell];
-
-            // Check possible directions
-            let mut rand_dirs: Vec<u8> = Vec::new();
-            if current_pos.0 < self.size.0 - 1 {
-                if self.data[current_pos.0 + 1][current_pos.1].is_visited() {
-                    rand_dirs.push(0); 
-                } else if !self.data[current_pos.0 + 1][current_pos.1].is_listed() {
-                    to_be_generated.push((current_pos.0 + 1, current_pos.1));
-                    self.data[current_pos.0 + 1][current_pos.1].list();
-                }
+    // Join parts of the maze that cannot reach each other by opening
+    // random walls between them. Row based algorithms can leave such
+    // parts when the mask cuts through their rows.
+    fn connect_parts<R: RngCore>(
+        &mut self,
+        rng: &mut R
+    ) {
+
+        // Crossings are two cells, one per passage
+        let count = self.cell_count();
+        let node = |maze: &Maze2, cell: usize, slot: u8| -> usize {
+            let (x, y) = maze.cell_pos(cell);
+            return if maze.is_crossing(x, y) && slot >= 2 { count + cell } else { cell };
+        };
+        let mut parents: Vec<usize> = (0..2 * count).collect();
+        let find = |parents: &mut Vec<usize>, mut cell: usize| -> usize {
+            while parents[cell] != cell {
+                parents[cell] = parents[parents[cell]];
+                cell = parents[cell];
             }
-            if current_pos.0 > 0 {
-                if self.data[current_pos.0 - 1][current_pos.1].is_visited() {
-                    rand_dirs.push(1); 
-                } else if !self.data[current_pos.0 - 1][current_pos.1].is_listed() {
-                    to_be_generated.push((current_pos.0 - 1, current_pos.1));
-                    self.data[current_pos.0 - 1][current_pos.1].list();
+            return cell;
+        };
+        let mut walls: Vec<(usize, u8, usize)> = Vec::new();
+        for cell in 0..count {
+            for &(slot, next) in self.adjacent(cell).iter() {
+                if next < cell {
+                    continue;
                 }
-            }
-            if current_pos.1 < self.size.1 - 1 {
-                if self.data[current_pos.0][current_pos.1 + 1].is_visited() {
-                    rand_dirs.push(2); 
-                } else if !self.data[current_pos.0][current_pos.1 + 1].is_listed() {
-                    to_be_generated.push((current_pos.0, current_pos.1 + 1));
-                    self.data[current_pos.0][current_pos.1 + 1].list();
+                let (a, b) = (node(self, cell, slot), node(self, next, opposite_dir(slot)));
+                if self.is_linked(cell, slot) {
+                    let (a, b) = (find(&mut parents, a), find(&mut parents, b));
+                    parents[a] = b;
+                } else {
+                    walls.push((cell, slot, next));
                 }
             }
-            if current_pos.1 > 0 {
-                if self.data[current_pos.0][current_pos.1 - 1].is_visited() {
-                    rand_dirs.push(3); 
-                } else if !self.data[current_pos.0][current_pos.1 - 1].is_listed() {
-                    to_be_generated.push((current_pos.0, current_pos.1 - 1));
-                    self.data[current_pos.0][current_pos.1 - 1].list();
-                }
+        }
+        walls.retain(|&(cell, _, next)| {
+            return find(&mut parents, cell) != find(&mut parents, next);
+        });
+        shuffle(rng, &mut walls);
+        for (cell, slot, next) in walls {
+            let (a, b) = (find(&mut parents, cell), find(&mut parents, next));
+            if a != b {
+                parents[a] = b;
+                self.link(cell, slot);
             }
+        }
 
-            // Select random directions and open passage
-            let rand_dir: u8 = rand_dirs[rng.gen_range(0, rand_dirs.len())];
-            self.data[current_pos.0][current_pos.1].open(rand_dir);
-            match rand_dir {
-                0 => {
-                    self.data[current_pos.0 + 1][current_pos.1].open(1);
-                }
-                1 => {
-                    self.data[current_pos.0 - 1][current_pos.1].open(0);
-                }
-                2 => {
-                    self.data[current_pos.0][current_pos.1 + 1].open(3);
-                }
-                3 => {
-                    self.data[current_pos.0][current_pos.1 - 1].open(2);
+    }
+
+    // Generate maze from a seed with Prim's algorithm, one step at a
+    // time. Gives the same maze as `generate_with_seed`. The maze counts
+    // as generated as soon as the steps are created.
+    pub fn generate_steps(
+        &mut self,
+        seed: u64
+    ) -> PrimSteps<&mut Maze2, ChaCha8Rng> {
+
+        self.start_steps(seed);
+        return PrimSteps::new(self, ChaCha8Rng::seed_from_u64(seed));
+
+    }
+
+    // Same as `generate_steps`, with the steps taking the maze along.
+    // Get it back with `into_maze`.
+    pub fn into_steps(
+        mut self,
+        seed: u64
+    ) -> PrimSteps<Maze2, ChaCha8Rng> {
+
+        self.start_steps(seed);
+        return PrimSteps::new(self, ChaCha8Rng::seed_from_u64(seed));
+
+    }
+
+    fn start_steps(
+        &mut self,
+        seed: u64
+    ) {
+        if self.generated {
+            panic!("Maze already generated!")
+        }
+        self.generated = true;
+        self.seed = Some(seed);
+    }
+
+    // Mark the maze as generated, e.g. after loading it from a file.
+    pub fn set_generated(
+        &mut self,
+        seed: Option<u64>
+    ) {
+        self.generated = true;
+        self.seed = seed;
+    }
+
+    pub fn draw_text(&self) {
+        print!("{}", self.to_text());
+    }
+
+}
+
+// Cells are numbered x-major, skipping masked out cells. Slots are the
+// four directions.
+impl Maze2 {
+
+    fn cell_pos(
+        &self,
+        cell: usize
+    ) -> (usize, usize) {
+        match self.mask {
+            Some(ref mask) => mask.active_cells()[cell],
+            None => (cell / self.size.1, cell % self.size.1)
+        }
+    }
+
+    fn cell_number(
+        &self,
+        x: usize,
+        y: usize
+    ) -> usize {
+        match self.mask {
+            Some(ref mask) => mask.cell_index(x, y).unwrap(),
+            None => x * self.size.1 + y
+        }
+    }
+
+}
+
+impl MazeGraph for Maze2 {
+
+    fn cell_count(&self) -> usize {
+        match self.mask {
+            Some(ref mask) => mask.active_cells().len(),
+            None => self.size.0 * self.size.1
+        }
+    }
+
+    fn adjacent(
+        &self,
+        cell: usize
+    ) -> Adjacent {
+
+        let (x, y) = self.cell_pos(cell);
+        let mut cells = Adjacent::new();
+        for dir in 0..4 {
+            if let Some((nx, ny)) = self.neighbour(x, y, dir) {
+                cells.push(dir, self.cell_number(nx, ny));
+            }
+        }
+        return cells;
+
+    }
+
+    fn is_linked(
+        &self,
+        cell: usize,
+        slot: u8
+    ) -> bool {
+        let (x, y) = self.cell_pos(cell);
+        return self.is_cell_open(x, y, slot);
+    }
+
+    fn link(
+        &mut self,
+        cell: usize,
+        slot: u8
+    ) {
+        let (x, y) = self.cell_pos(cell);
+        self.carve_cell(x, y, slot);
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    // Between any two events, the frontier is exactly the listed cells,
+    // none of them visited and each next to a visited cell, and passages
+    // are only carved towards visited cells.
+    #[test]
+    fn prim_steps_keep_frontier_and_visited_cells_apart() {
+
+        let (size_x, size_y) = (12, 9);
+        let mut maze = Maze2::empty(size_x, size_y);
+        let mut steps = maze.generate_steps(7);
+        let mut visited: usize = 0;
+        while let Some(event) = steps.next() {
+            match event {
+                GenEvent::Visit(_, _) => visited += 1,
+                GenEvent::Carve(x, y, dir) => {
+                    let (nx, ny) = steps.borrow_maze().neighbour(x, y, dir).unwrap();
+                    assert!(steps.is_visited(nx, ny));
                 }
                 _ => {}
             }
 
-            // Mark cell as visisted
-            self.data[current_pos.0][current_pos.1].visit();
-            // Unlist cell
-            self.data[current_pos.0][current_pos.1].unlist();
-            to_be_generated.swap_remove(rand_cell);
+            let maze = steps.borrow_maze();
+            let frontier = steps.frontier();
+            let mut listed: usize = 0;
+            for x in 0..size_x {
+                for y in 0..size_y {
+                    if !steps.is_listed(x, y) {
+                        continue;
+                    }
+                    listed += 1;
+                    assert!(frontier.contains(&(x, y)));
+                    assert!(!steps.is_visited(x, y));
+                    assert!((0..4).any(|dir| {
+                        return maze.neighbour(x, y, dir).map_or(false, |(nx, ny)| steps.is_visited(nx, ny));
+                    }));
+                }
+            }
+            assert_eq!(listed, frontier.len());
         }
+        assert!(steps.is_done());
+        assert_eq!(visited, size_x * size_y);
 
-        self.generated = true;
+        // Same maze as generating it in one go
+        let mut other = Maze2::empty(size_x, size_y);
+        other.generate_with_seed(7);
+        assert!(maze.borrow_data() == other.borrow_data());
 
     }
 
-    pub fn draw_text(&self) {
+    // Each step lists the new neighbours of a frontier cell, then carves
+    // its passage, visits it and unlists it. Every active cell is visited
+    // once and joined by exactly one passage, except the first.
+    #[test]
+    fn prim_steps_visit_every_cell_once_in_order() {
 
-        for row in self.data.iter() {
-            for cell in (*row).iter() {
-                print!("██");
-                if (*cell).is_open(2) {
-                    print!("██");
-                } else {
-                    print!("  ");
+        let mask = MazeMask::from_text("#####.\n##.###\n######\n.###.#\n").unwrap();
+        let active = mask.active_cells().len();
+        let mut steps = Maze2::masked(mask.clone()).into_steps(11);
+        let events: Vec<GenEvent> = steps.by_ref().collect();
+        assert!(steps.is_done());
+
+        let mut visited: Vec<(usize, usize)> = Vec::new();
+        let mut listed: Vec<(usize, usize)> = Vec::new();
+        let mut carved: usize = 0;
+        let mut k: usize = 0;
+        match events[0] {
+            GenEvent::Visit(x, y) => visited.push((x, y)),
+            other => panic!("Started with {:?}", other)
+        }
+        k += 1;
+        while k < events.len() {
+            match events[k] {
+                GenEvent::List(x, y) => {
+                    assert!(mask.is_active(x, y));
+                    assert!(!listed.contains(&(x, y)) && !visited.contains(&(x, y)));
+                    listed.push((x, y));
+                    k += 1;
                 }
-            }
-            print!("\n");
-            for cell in (*row).iter() {
-                if (*cell).is_open(0) {
-                    print!("██");
-                } else {
-                    print!("  ");
+                GenEvent::Carve(x, y, _) => {
+                    assert!(listed.contains(&(x, y)));
+                    assert_eq!(events[k + 1], GenEvent::Visit(x, y));
+                    assert_eq!(events[k + 2], GenEvent::Unlist(x, y));
+                    assert!(!visited.contains(&(x, y)));
+                    visited.push((x, y));
+                    listed.retain(|&pos| pos != (x, y));
+                    carved += 1;
+                    k += 3;
                 }
-                print!("  ");
+                other => panic!("Out of order: {:?}", other)
             }
-            print!("\n");
         }
-    
+        assert!(listed.is_empty());
+        assert_eq!(visited.len(), active);
+        assert_eq!(carved, active - 1);
+
+        // The steps hand back the finished maze
+        let maze = steps.into_maze();
+        let mut other = Maze2::masked(mask);
+        other.generate_with_seed(11);
+        assert_eq!(maze.get_seed(), Some(11));
+        assert!(maze.borrow_data() == other.borrow_data());
+
+    }
+
+    #[test]
+    fn directions_match_neighbours() {
+
+        let maze = Maze2::empty(5, 5);
+        for &dir in Direction::ALL.iter() {
+            assert_eq!(Direction::from_u8(dir.as_u8()), Some(dir));
+            assert_eq!(dir.opposite().opposite(), dir);
+            assert_eq!(dir.opposite().axis(), dir.axis());
+            let (dx, dy) = dir.offset();
+            let next = ((2 + dx) as usize, (2 + dy) as usize);
+            assert_eq!(maze.next_cell((2, 2), dir), Some(next));
+            assert_eq!(maze.next_cell(next, dir.opposite()), Some((2, 2)));
+        }
+        assert_eq!(Direction::from_u8(4), None);
+
+    }
+
+    #[test]
+    fn carve_and_wall_change_both_sides() {
+
+        let mut maze = Maze2::empty(4, 3);
+        maze.set_wrap(true, false);
+
+        assert_eq!(maze.carve((1, 1), Direction::PosY), Ok(()));
+        assert_eq!(maze.is_open((1, 1), Direction::PosY), Ok(true));
+        assert_eq!(maze.is_open((1, 2), Direction::NegY), Ok(true));
+        assert_eq!(maze.wall((1, 2), Direction::NegY), Ok(()));
+        assert_eq!(maze.is_open((1, 1), Direction::PosY), Ok(false));
+        assert_eq!(maze.is_open((1, 2), Direction::NegY), Ok(false));
+
+        // Across the seam of a wrapping axis
+        assert_eq!(maze.carve((0, 0), Direction::NegX), Ok(()));
+        assert_eq!(maze.is_open((3, 0), Direction::PosX), Ok(true));
+        assert_eq!(maze.wall((3, 0), Direction::PosX), Ok(()));
+        assert_eq!(maze.is_open((0, 0), Direction::NegX), Ok(false));
+
+    }
+
+    #[test]
+    fn accessors_fail_outside_and_on_masked_cells() {
+
+        let mask = MazeMask::from_text("##\n#.\n").unwrap();
+        let mut maze = Maze2::masked(mask);
+
+        assert!(maze.get_cell((1, 1)).is_some());
+        assert!(maze.get_cell((2, 0)).is_none());
+        assert_eq!(maze.is_open((0, 2), Direction::PosX), Err(CellError::OutOfBounds(0, 2)));
+        assert_eq!(maze.next_cell((0, 0), Direction::NegX), None);
+        assert_eq!(maze.next_cell((1, 0), Direction::PosY), None);
+        assert_eq!(maze.next_cell((2, 0), Direction::NegX), None);
+        assert_eq!(maze.neighbours((0, 0)).count(), 2);
+        assert_eq!(maze.neighbours((1, 0)).collect::<Vec<_>>(), vec![(Direction::NegX, (0, 0))]);
+
+        assert_eq!(maze.carve((5, 5), Direction::PosX), Err(CellError::OutOfBounds(5, 5)));
+        assert_eq!(maze.carve((1, 1), Direction::NegX), Err(CellError::MaskedOut(1, 1)));
+        assert_eq!(maze.carve((0, 0), Direction::NegX), Err(CellError::NoNeighbour(0, 0, Direction::NegX)));
+        assert_eq!(maze.carve((1, 0), Direction::PosY), Err(CellError::NoNeighbour(1, 0, Direction::PosY)));
+        assert_eq!(maze.wall((0, 9), Direction::NegY), Err(CellError::OutOfBounds(0, 9)));
+        assert_eq!(maze.wall((0, 0), Direction::NegX), Ok(()));
+
     }
 
 }
@@ -0,0 +1,615 @@
+// Non-rectangular maze grids: hexagonal, triangular and polar.
+//
+// A `Grid` describes the topology (which cell is reached through which
+// slot) and the geometry (the outline of every cell, in maze units) of
+// a grid. `GridMaze` stores the carved passages for any grid and can be
+// generated with any `GraphGenerator`.
+
+extern crate rand;
+extern crate rand_chacha;
+
+use rand::SeedableRng;
+use rand::RngCore;
+use rand_chacha::ChaCha8Rng;
+use crate::mazegen::*;
+use crate::mazealgo::GraphGenerator;
+
+const SQRT_3: f32 = 1.7320508;
+
+// One side of a cell outline. It is shared with the cell through `slot`,
+// or part of the outer boundary if there is no cell there.
+pub struct Side {
+    pub slot: u8,
+    pub points: Vec<(f32, f32)>
+}
+
+pub trait Grid {
+
+    fn cell_count(&self) -> usize;
+
+    // Cell reached through a slot, if any.
+    fn neighbour(
+        &self,
+        cell: usize,
+        slot: u8
+    ) -> Option<usize>;
+
+    // Slot of the neighbour that leads back to the cell.
+    fn back_slot(
+        &self,
+        cell: usize,
+        slot: u8
+    ) -> u8;
+
+    // Highest slot count of any cell.
+    fn slot_count(&self) -> u8;
+
+    // Outline of a cell.
+    fn sides(
+        &self,
+        cell: usize
+    ) -> Vec<Side>;
+
+}
+
+// Hexagonal grid of pointy-top cells, one unit wide, with odd rows
+// shifted half a cell along +x.
+// Slots: 0 = +x, 1 = -x, 2 = +x+y, 3 = -x-y, 4 = -x+y, 5 = +x-y.
+pub struct HexGrid {
+    size: (usize, usize)
+}
+
+impl HexGrid {
+
+    pub fn new(
+        size_x: usize,
+        size_y: usize
+    ) -> HexGrid {
+        return HexGrid {
+            size: (size_x, size_y)
+        };
+    }
+
+    fn corner(
+        &self,
+        x: usize,
+        y: usize,
+        i: usize
+    ) -> (f32, f32) {
+
+        let radius = 1.0 / SQRT_3;
+        let cx = x as f32 + 0.5 * (y % 2) as f32 + 0.5;
+        let cy = y as f32 * 1.5 * radius + radius;
+        let angle = (30.0 + 60.0 * i as f32).to_radians();
+        return (cx + radius * angle.cos(), cy + radius * angle.sin());
+
+    }
+
+}
+
+impl Grid for HexGrid {
+
+    fn cell_count(&self) -> usize {
+        return self.size.0 * self.size.1;
+    }
+
+    fn neighbour(
+        &self,
+        cell: usize,
+        slot: u8
+    ) -> Option<usize> {
+
+        let (x, y) = ((cell / self.size.1) as isize, (cell % self.size.1) as isize);
+        let odd = y % 2;
+        let (nx, ny): (isize, isize) = match slot {
+            0 => (x + 1, y),
+            1 => (x - 1, y),
+            2 => (x + odd, y + 1),
+            3 => (x + odd - 1, y - 1),
+            4 => (x + odd - 1, y + 1),
+            5 => (x + odd, y - 1),
+            _ => return None
+        };
+        if nx < 0 || ny < 0 || nx >= self.size.0 as isize || ny >= self.size.1 as isize {
+            return None;
+        }
+        return Some(nx as usize * self.size.1 + ny as usize);
+
+    }
+
+    fn back_slot(
+        &self,
+        _cell: usize,
+        slot: u8
+    ) -> u8 {
+        return slot ^ 1;
+    }
+
+    fn slot_count(&self) -> u8 {
+        return 6;
+    }
+
+    fn sides(
+        &self,
+        cell: usize
+    ) -> Vec<Side> {
+
+        let (x, y) = (cell / self.size.1, cell % self.size.1);
+        // Corner pairs of each slot, counter-clockwise from +x
+        let corners: [(u8, usize, usize); 6] = [
+            (0, 5, 0), (2, 0, 1), (4, 1, 2), (1, 2, 3), (3, 3, 4), (5, 4, 5)
+        ];
+        return corners.iter().map(|&(slot, a, b)| Side {
+            slot: slot,
+            points: vec![self.corner(x, y, a), self.corner(x, y, b)]
+        }).collect();
+
+    }
+
+}
+
+// Triangular grid of unit triangles, alternating between pointing +y
+// and -y along each row.
+// Slots: 0 = +x, 1 = -x, 2 = +y (cells pointing -y), 3 = -y (cells
+// pointing +y).
+pub struct TriGrid {
+    size: (usize, usize)
+}
+
+impl TriGrid {
+
+    pub fn new(
+        size_x: usize,
+        size_y: usize
+    ) -> TriGrid {
+        return TriGrid {
+            size: (size_x, size_y)
+        };
+    }
+
+    fn points_up(
+        &self,
+        x: usize,
+        y: usize
+    ) -> bool {
+        return (x + y) % 2 == 0;
+    }
+
+}
+
+impl Grid for TriGrid {
+
+    fn cell_count(&self) -> usize {
+        return self.size.0 * self.size.1;
+    }
+
+    fn neighbour(
+        &self,
+        cell: usize,
+        slot: u8
+    ) -> Option<usize> {
+
+        let (x, y) = (cell / self.size.1, cell % self.size.1);
+        let up = self.points_up(x, y);
+        let (nx, ny): (usize, usize) = match slot {
+            0 if x + 1 < self.size.0 => (x + 1, y),
+            1 if x > 0 => (x - 1, y),
+            2 if !up && y + 1 < self.size.1 => (x, y + 1),
+            3 if up && y > 0 => (x, y - 1),
+            _ => return None
+        };
+        return Some(nx * self.size.1 + ny);
+
+    }
+
+    fn back_slot(
+        &self,
+        _cell: usize,
+        slot: u8
+    ) -> u8 {
+        return slot ^ 1;
+    }
+
+    fn slot_count(&self) -> u8 {
+        return 4;
+    }
+
+    fn sides(
+        &self,
+        cell: usize
+    ) -> Vec<Side> {
+
+        let (x, y) = (cell / self.size.1, cell % self.size.1);
+        let height = SQRT_3 / 2.0;
+        let left = x as f32 * 0.5;
+        let (base_y, tip_y, base_slot) = if self.points_up(x, y) {
+            (y as f32 * height, (y + 1) as f32 * height, 3)
+        } else {
+            ((y + 1) as f32 * height, y as f32 * height, 2)
+        };
+        let base_a = (left, base_y);
+        let base_b = (left + 1.0, base_y);
+        let tip = (left + 0.5, tip_y);
+        return vec![
+            Side { slot: 0, points: vec![base_b, tip] },
+            Side { slot: 1, points: vec![base_a, tip] },
+            Side { slot: base_slot, points: vec![base_a, base_b] }
+        ];
+
+    }
+
+}
+
+// Polar (circular) grid of unit-wide rings around a single center cell.
+// Rings are split into more cells as they grow, so a cell may have more
+// than one outward neighbour.
+// Slots: 0 = next cell in the ring, 1 = previous cell in the ring,
+// 2 = inward, 3 and up = outward, in ring order.
+pub struct PolarGrid {
+    counts: Vec<usize>,     // Cell count of each ring
+    offsets: Vec<usize>     // Index of the first cell of each ring
+}
+
+impl PolarGrid {
+
+    pub fn new(
+        rings: usize
+    ) -> PolarGrid {
+
+        let mut grid = PolarGrid {
+            counts: Vec::new(),
+            offsets: Vec::new()
+        };
+        let mut offset: usize = 0;
+        for ring in 0..rings {
+            let count: usize = if ring == 0 {
+                1
+            } else {
+                // Keep cells roughly one unit wide along the ring
+                let prev = grid.counts[ring - 1];
+                let circumference = 2.0 * std::f32::consts::PI * ring as f32;
+                let ratio = (circumference / prev as f32).round().max(1.0);
+                prev * ratio as usize
+            };
+            grid.counts.push(count);
+            grid.offsets.push(offset);
+            offset += count;
+        }
+        return grid;
+
+    }
+
+    pub fn ring_count(&self) -> usize {
+        return self.counts.len();
+    }
+
+    // Ring and index in the ring of a cell.
+    pub fn ring_pos(
+        &self,
+        cell: usize
+    ) -> (usize, usize) {
+
+        let ring = match self.offsets.binary_search(&cell) {
+            Ok(ring) => ring,
+            Err(ring) => ring - 1
+        };
+        return (ring, cell - self.offsets[ring]);
+
+    }
+
+    // How many cells of a ring share one cell of the ring inside it.
+    fn ratio(
+        &self,
+        ring: usize
+    ) -> usize {
+        return self.counts[ring] / self.counts[ring - 1];
+    }
+
+    fn arc(
+        &self,
+        radius: f32,
+        from: f32,
+        to: f32
+    ) -> Vec<(f32, f32)> {
+
+        let center = self.ring_count() as f32;
+        let steps = ((to - from) * radius * 4.0).ceil().max(1.0) as usize;
+        return (0..steps + 1).map(|i| {
+            let angle = from + (to - from) * i as f32 / steps as f32;
+            return (center + radius * angle.cos(), center + radius * angle.sin());
+        }).collect();
+
+    }
+
+}
+
+impl Grid for PolarGrid {
+
+    fn cell_count(&self) -> usize {
+        return self.offsets.last().map_or(0, |o| o + self.counts[self.counts.len() - 1]);
+    }
+
+    fn neighbour(
+        &self,
+        cell: usize,
+        slot: u8
+    ) -> Option<usize> {
+
+        let (ring, i) = self.ring_pos(cell);
+        let count = self.counts[ring];
+        return match slot {
+            0 if count > 1 => Some(self.offsets[ring] + (i + 1) % count),
+            1 if count > 1 => Some(self.offsets[ring] + (i + count - 1) % count),
+            2 if ring > 0 => Some(self.offsets[ring - 1] + i / self.ratio(ring)),
+            _ if slot >= 3 && ring + 1 < self.ring_count() => {
+                let ratio = self.ratio(ring + 1);
+                let k = (slot - 3) as usize;
+                if k < ratio {
+                    Some(self.offsets[ring + 1] + i * ratio + k)
+                } else {
+                    None
+                }
+            }
+            _ => None
+        };
+
+    }
+
+    fn back_slot(
+        &self,
+        cell: usize,
+        slot: u8
+    ) -> u8 {
+
+        return match slot {
+            0 => 1,
+            1 => 0,
+            2 => {
+                let (ring, i) = self.ring_pos(cell);
+                3 + (i % self.ratio(ring)) as u8
+            }
+            _ => 2
+        };
+
+    }
+
+    fn slot_count(&self) -> u8 {
+        return 3 + (1..self.ring_count()).map(|r| self.ratio(r)).max().unwrap_or(1) as u8;
+    }
+
+    fn sides(
+        &self,
+        cell: usize
+    ) -> Vec<Side> {
+
+        let (ring, i) = self.ring_pos(cell);
+        let theta = 2.0 * std::f32::consts::PI / self.counts[ring] as f32;
+        let (from, to) = (i as f32 * theta, (i + 1) as f32 * theta);
+        let inner = ring as f32;
+        let outer = (ring + 1) as f32;
+        let mut sides: Vec<Side> = Vec::new();
+
+        if ring > 0 {
+            let center = self.ring_count() as f32;
+            let radial = |angle: f32| -> Vec<(f32, f32)> {
+                return vec![
+                    (center + inner * angle.cos(), center + inner * angle.sin()),
+                    (center + outer * angle.cos(), center + outer * angle.sin())
+                ];
+            };
+            sides.push(Side { slot: 0, points: radial(to) });
+            sides.push(Side { slot: 1, points: radial(from) });
+            sides.push(Side { slot: 2, points: self.arc(inner, from, to) });
+        }
+        if ring + 1 < self.ring_count() {
+            let ratio = self.ratio(ring + 1);
+            let step = theta / ratio as f32;
+            for k in 0..ratio {
+                sides.push(Side {
+                    slot: 3 + k as u8,
+                    points: self.arc(outer, from + k as f32 * step, from + (k + 1) as f32 * step)
+                });
+            }
+        } else {
+            sides.push(Side { slot: 3, points: self.arc(outer, from, to) });
+        }
+        return sides;
+
+    }
+
+}
+
+// A maze on any grid. Passages are stored as one bit per slot.
+pub struct GridMaze<G: Grid> {
+    grid: G,
+    generated: bool,
+    seed: Option<u64>,
+    links: Vec<u16>
+}
+
+impl<G: Grid> GridMaze<G> {
+
+    pub fn empty(
+        grid: G
+    ) -> GridMaze<G> {
+
+        let count = grid.cell_count();
+        return GridMaze {
+            grid: grid,
+            generated: false,
+            seed: None,
+            links: vec![0; count]
+        };
+
+    }
+
+    pub fn borrow_grid(&self) -> &G {
+        return &self.grid;
+    }
+
+    pub fn get_seed(&self) -> Option<u64> {
+        return self.seed;
+    }
+
+    // Generate maze from a seed with the given algorithm.
+    pub fn generate_using<T: GraphGenerator + ?Sized>(
+        &mut self,
+        generator: &T,
+        seed: u64
+    ) {
+
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        self.generate_using_rng(generator, &mut rng);
+        self.seed = Some(seed);
+
+    }
+
+    // Generate maze with the given algorithm and random number generator.
+    pub fn generate_using_rng<T: GraphGenerator + ?Sized, R: RngCore>(
+        &mut self,
+        generator: &T,
+        rng: &mut R
+    ) {
+
+        if self.generated {
+            panic!("Maze already generated!")
+        }
+        generator.generate_graph(self, rng);
+        self.generated = true;
+
+    }
+
+    // Standing walls of a cell, as polylines. A wall shared with another
+    // cell belongs to the lower numbered of the two.
+    pub fn cell_walls(
+        &self,
+        cell: usize
+    ) -> Vec<Vec<(f32, f32)>> {
+
+        let mut walls: Vec<Vec<(f32, f32)>> = Vec::new();
+        for side in self.grid.sides(cell) {
+            if self.is_linked(cell, side.slot) {
+                continue;
+            }
+            match self.grid.neighbour(cell, side.slot) {
+                Some(next) if next < cell => {}
+                _ => walls.push(side.points)
+            }
+        }
+        return walls;
+
+    }
+
+    // Standing walls of the whole maze, as polylines.
+    pub fn walls(&self) -> Vec<Vec<(f32, f32)>> {
+
+        let mut walls: Vec<Vec<(f32, f32)>> = Vec::new();
+        for cell in 0..self.grid.cell_count() {
+            walls.extend(self.cell_walls(cell));
+        }
+        return walls;
+
+    }
+
+    // Rasterize the walls into text, `resolution` characters per maze
+    // unit, in the same block style as `Maze2::draw_text`.
+    pub fn to_text(
+        &self,
+        resolution: usize
+    ) -> String {
+
+        let walls = self.walls();
+        let res = resolution.max(1) as f32;
+        let mut min = (std::f32::MAX, std::f32::MAX);
+        let mut max = (std::f32::MIN, std::f32::MIN);
+        for point in walls.iter().flat_map(|w| w.iter()) {
+            min = (min.0.min(point.0), min.1.min(point.1));
+            max = (max.0.max(point.0), max.1.max(point.1));
+        }
+
+        let mut text = String::new();
+        if let Some(seed) = self.seed {
+            text.push_str(&format!("Seed: {}\n", seed));
+        }
+        if walls.len() == 0 {
+            return text;
+        }
+
+        let width = ((max.0 - min.0) * res).round() as usize + 1;
+        let height = ((max.1 - min.1) * res).round() as usize + 1;
+        let mut canvas: Vec<Vec<bool>> = vec![vec![false; width]; height];
+        for wall in walls.iter() {
+            for segment in wall.windows(2) {
+                let (a, b) = (segment[0], segment[1]);
+                let length = ((b.0 - a.0).powi(2) + (b.1 - a.1).powi(2)).sqrt();
+                let steps = (length * res * 2.0).ceil().max(1.0) as usize;
+                for i in 0..steps + 1 {
+                    let t = i as f32 / steps as f32;
+                    let px = ((a.0 + (b.0 - a.0) * t - min.0) * res).round() as usize;
+                    let py = ((a.1 + (b.1 - a.1) * t - min.1) * res).round() as usize;
+                    canvas[py.min(height - 1)][px.min(width - 1)] = true;
+                }
+            }
+        }
+
+        // Print +y upwards
+        for row in canvas.iter().rev() {
+            for filled in row.iter() {
+                text.push_str(if *filled { "██" } else { "  " });
+            }
+            text.push('\n');
+        }
+        return text;
+
+    }
+
+    pub fn draw_text(&self) {
+        print!("{}", self.to_text(4));
+    }
+
+}
+
+impl<G: Grid> MazeGraph for GridMaze<G> {
+
+    fn cell_count(&self) -> usize {
+        return self.grid.cell_count();
+    }
+
+    fn adjacent(
+        &self,
+        cell: usize
+    ) -> Adjacent {
+
+        let mut cells = Adjacent::new();
+        for slot in 0..self.grid.slot_count() {
+            if let Some(next) = self.grid.neighbour(cell, slot) {
+                cells.push(slot, next);
+            }
+        }
+        return cells;
+
+    }
+
+    fn is_linked(
+        &self,
+        cell: usize,
+        slot: u8
+    ) -> bool {
+        return self.links[cell] >> slot & 1 == 1;
+    }
+
+    fn link(
+        &mut self,
+        cell: usize,
+        slot: u8
+    ) {
+
+        if let Some(next) = self.grid.neighbour(cell, slot) {
+            let back = self.grid.back_slot(cell, slot);
+            self.links[cell] |= 1 << slot;
+            self.links[next] |= 1 << back;
+        }
+
+    }
+
+}
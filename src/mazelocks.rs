@@ -0,0 +1,255 @@
+// Keys and locked doors.
+//
+// Doors block passages on the way from start to goal until the key of
+// the same colour has been picked up. Keys are hidden in side branches
+// reachable without going through the door they open, so the goal can
+// always be reached by collecting them in order.
+
+extern crate rand;
+
+use rand::Rng;
+use crate::mazegen::*;
+
+// Door on the passage leaving a cell through `dir`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Door {
+    pub x: usize,
+    pub y: usize,
+    pub dir: u8,
+    pub colour: usize   // Index of the key opening the door
+}
+
+pub struct Locks {
+    pub doors: Vec<Door>,
+    pub keys: Vec<(usize, usize)>,  // Cell of the key of every colour
+    sides: Vec<[Option<usize>; 4]>  // Door colour on every side of every cell, see `door_at`
+}
+
+impl Locks {
+
+    pub fn new() -> Locks {
+        return Locks {
+            doors: Vec::new(),
+            keys: Vec::new(),
+            sides: Vec::new()
+        };
+    }
+
+    // Place up to `count` doors along the path from `start` to `goal`,
+    // spread evenly, each with its key. Fewer doors are placed if the
+    // path is too short, or if there is nowhere to hide a door's key.
+    pub fn place<R: Rng + ?Sized>(
+        maze: &Maze2,
+        count: usize,
+        start: (usize, usize),
+        goal: (usize, usize),
+        rng: &mut R
+    ) -> Locks {
+
+        let mut locks = Locks::new();
+        let path = maze.solve(start, goal).unwrap_or(vec![start]);
+        let steps = path.len() - 1;
+
+        // Doors on the passages nearest to even intervals, never on
+        // crossings as they would block both passages
+        let mut last: Option<usize> = None;
+        for i in 0..count {
+            let target = (i + 1) * steps / (count + 1);
+            let mut candidates: Vec<usize> = (0..steps)
+                .filter(|&k| last.map_or(true, |l| k > l))
+                .filter(|&k| !maze.is_crossing(path[k].0, path[k].1))
+                .filter(|&k| !maze.is_crossing(path[k + 1].0, path[k + 1].1))
+                .collect();
+            candidates.sort_by_key(|&k| (k as isize - target as isize).abs());
+            let k = match candidates.first() {
+                Some(&k) => k,
+                None => break
+            };
+            locks.doors.push(Door {
+                x: path[k].0,
+                y: path[k].1,
+                dir: maze.dir_towards(path[k], path[k + 1]).unwrap(),
+                colour: i
+            });
+            last = Some(k);
+        }
+
+        // Each key goes where its door's side of the maze was opened up
+        // by the door before, off the path and preferably in a dead end
+        let size = maze.get_size();
+        let mut on_path: Vec<bool> = vec![false; size.0 * size.1];
+        for &(x, y) in path.iter() {
+            on_path[maze.index(x, y)] = true;
+        }
+        let mut before: Vec<bool> = vec![false; size.0 * size.1];
+        locks.index_doors(maze);
+        let mut i = 0;
+        while i < locks.doors.len() {
+            let reach = maze.reachable(start, |x, y, dir| {
+                return Direction::from_u8(dir)
+                    .and_then(|dir| locks.door_at(maze, x, y, dir))
+                    .map_or(false, |c| c >= i);
+            });
+            let mut fresh: Vec<(usize, usize)> = Vec::new();
+            let mut dead_ends: Vec<(usize, usize)> = Vec::new();
+            let mut known: Vec<(usize, usize)> = Vec::new();
+            for x in 0..size.0 {
+                for y in 0..size.1 {
+                    let cell = maze.index(x, y);
+                    if !reach[cell] || on_path[cell] {
+                        continue;
+                    }
+                    known.push((x, y));
+                    if !before[cell] {
+                        fresh.push((x, y));
+                        if maze.open_count(x, y) == 1 {
+                            dead_ends.push((x, y));
+                        }
+                    }
+                }
+            }
+            let cells = if dead_ends.len() > 0 {
+                dead_ends
+            } else if fresh.len() > 0 {
+                fresh
+            } else {
+                known
+            };
+
+            // No cell off the path before the door: drop it, so the
+            // next door takes its colour and opens up its side too
+            if cells.is_empty() {
+                locks.doors.remove(i);
+                for door in locks.doors[i..].iter_mut() {
+                    door.colour -= 1;
+                }
+                locks.index_doors(maze);
+                continue;
+            }
+            locks.keys.push(cells[rand_index(rng, cells.len())]);
+            before = reach;
+            i += 1;
+        }
+
+        return locks;
+
+    }
+
+    // Record the colour of every door on both sides of its passage.
+    fn index_doors(
+        &mut self,
+        maze: &Maze2
+    ) {
+
+        let size = maze.get_size();
+        self.sides = vec![[None; 4]; size.0 * size.1];
+        for door in self.doors.iter() {
+            let dir = Direction::from_u8(door.dir).unwrap();
+            self.sides[maze.index(door.x, door.y)][dir.as_u8() as usize] = Some(door.colour);
+            if let Some((x, y)) = maze.next_cell((door.x, door.y), dir) {
+                self.sides[maze.index(x, y)][dir.opposite().as_u8() as usize] = Some(door.colour);
+            }
+        }
+
+    }
+
+    // Colour of the door on a passage, looked up from either side.
+    pub fn door_at(
+        &self,
+        maze: &Maze2,
+        x: usize,
+        y: usize,
+        dir: Direction
+    ) -> Option<usize> {
+
+        return self.sides.get(maze.index(x, y)).and_then(|sides| sides[dir.as_u8() as usize]);
+
+    }
+
+    // Colours of the keys in an order they can be picked up in, up to
+    // the point where the goal can be reached. None if the goal stays
+    // locked away.
+    pub fn pickup_order(
+        &self,
+        maze: &Maze2,
+        start: (usize, usize),
+        goal: (usize, usize)
+    ) -> Option<Vec<usize>> {
+
+        let mut held: Vec<bool> = vec![false; self.keys.len()];
+        let mut order: Vec<usize> = Vec::new();
+        loop {
+            let reach = maze.reachable(start, |x, y, dir| {
+                return Direction::from_u8(dir)
+                    .and_then(|dir| self.door_at(maze, x, y, dir))
+                    .map_or(false, |c| !held[c]);
+            });
+            if reach[maze.index(goal.0, goal.1)] {
+                return Some(order);
+            }
+            let mut found = false;
+            for (colour, &(x, y)) in self.keys.iter().enumerate() {
+                if !held[colour] && reach[maze.index(x, y)] {
+                    held[colour] = true;
+                    order.push(colour);
+                    found = true;
+                }
+            }
+            if !found {
+                return None;
+            }
+        }
+
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use rand::SeedableRng;
+
+    #[test]
+    fn doors_block_both_sides_and_keys_open_them_in_order() {
+
+        for seed in 0..20 {
+            let mut maze = Maze2::empty(12, 9);
+            maze.generate_using(&Prim, seed);
+            let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(seed);
+            let (start, goal, _) = maze.farthest_pair();
+            let locks = Locks::place(&maze, 3, start, goal, &mut rng);
+
+            assert_eq!(locks.keys.len(), locks.doors.len());
+            for (colour, door) in locks.doors.iter().enumerate() {
+                let dir = Direction::from_u8(door.dir).unwrap();
+                let next = maze.next_cell((door.x, door.y), dir).unwrap();
+                assert_eq!(door.colour, colour);
+                assert_eq!(locks.door_at(&maze, door.x, door.y, dir), Some(colour));
+                assert_eq!(locks.door_at(&maze, next.0, next.1, dir.opposite()), Some(colour));
+            }
+            let order = locks.pickup_order(&maze, start, goal).unwrap();
+            assert_eq!(order, (0..locks.doors.len()).collect::<Vec<usize>>());
+        }
+
+    }
+
+    // A corridor has no cell off the path to hide a key in, so no door
+    // is left in it
+    #[test]
+    fn doors_without_room_for_their_key_are_dropped() {
+
+        let mut maze = Maze2::empty(8, 1);
+        for x in 0..7 {
+            maze.carve((x, 0), Direction::PosX).unwrap();
+        }
+        let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(0);
+        let locks = Locks::place(&maze, 2, (0, 0), (7, 0), &mut rng);
+
+        assert!(locks.doors.is_empty());
+        assert!(locks.keys.is_empty());
+        assert_eq!(locks.door_at(&maze, 3, 0, Direction::PosX), None);
+
+    }
+
+}
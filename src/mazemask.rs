@@ -0,0 +1,363 @@
+// Maze masks.
+//
+// A mask marks which cells of a maze are part of it, so mazes can take
+// the shape of letters, logos or rooms. Masks are read like the text
+// format: lines run along x, characters or pixels along y.
+//
+// ASCII templates: one character per cell, any non-blank character
+// other than '.' is an active cell.
+// PBM (P1 or P4) and PNG images: one pixel per cell, dark pixels are
+// active cells, transparent pixels are not.
+
+extern crate png;
+
+use std::io::Read;
+use std::collections::VecDeque;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    Png(png::DecodingError),
+    BadFormat,
+    Empty,
+}
+
+impl From<std::io::Error> for Error {
+
+    fn from(inp: std::io::Error) -> Self {
+        return Error::Io(inp);
+    }
+
+}
+
+impl From<png::DecodingError> for Error {
+
+    fn from(inp: png::DecodingError) -> Self {
+        return Error::Png(inp);
+    }
+
+}
+
+#[derive(Clone)]
+pub struct MazeMask {
+    size: (usize, usize),
+    active: Vec<bool>,
+    cells: Vec<(usize, usize)>,
+    index: Vec<Option<usize>>
+}
+
+// Mask files are recognized by their extension.
+pub fn is_mask_path<P: AsRef<std::path::Path>>(
+    path: P
+) -> bool {
+    return path.as_ref().extension().map_or(false, |e| {
+        e == "mask" || e == "pbm" || e == "png"
+    });
+}
+
+// Next header token of a PBM file, skipping blanks and comments.
+fn pbm_token<'a>(
+    bytes: &'a [u8],
+    pos: &mut usize
+) -> Option<&'a [u8]> {
+
+    loop {
+        match bytes.get(*pos) {
+            Some(b'#') => {
+                while bytes.get(*pos).map_or(false, |b| *b != b'\n') {
+                    *pos += 1;
+                }
+            }
+            Some(b) if b.is_ascii_whitespace() => *pos += 1,
+            Some(_) => break,
+            None => return None
+        }
+    }
+    let start = *pos;
+    while bytes.get(*pos).map_or(false, |b| !b.is_ascii_whitespace()) {
+        *pos += 1;
+    }
+    return Some(&bytes[start..*pos]);
+
+}
+
+fn pbm_number(
+    bytes: &[u8],
+    pos: &mut usize
+) -> Result<usize, Error> {
+
+    let token = pbm_token(bytes, pos).ok_or(Error::BadFormat)?;
+    return std::str::from_utf8(token).ok()
+        .and_then(|s| s.parse::<usize>().ok())
+        .ok_or(Error::BadFormat);
+
+}
+
+impl MazeMask {
+
+    // Mask from a grid of flags, x-major.
+    pub fn new(
+        size_x: usize,
+        size_y: usize,
+        active: Vec<bool>
+    ) -> MazeMask {
+
+        let mut cells: Vec<(usize, usize)> = Vec::new();
+        let mut index: Vec<Option<usize>> = vec![None; size_x * size_y];
+        for x in 0..size_x {
+            for y in 0..size_y {
+                if active[x * size_y + y] {
+                    index[x * size_y + y] = Some(cells.len());
+                    cells.push((x, y));
+                }
+            }
+        }
+        return MazeMask {
+            size: (size_x, size_y),
+            active: active,
+            cells: cells,
+            index: index
+        };
+
+    }
+
+    pub fn get_size(&self) -> (usize, usize) {
+        return self.size;
+    }
+
+    pub fn is_active(
+        &self,
+        x: usize,
+        y: usize
+    ) -> bool {
+        return self.active[x * self.size.1 + y];
+    }
+
+    // Active cells, x-major.
+    pub fn active_cells(&self) -> &Vec<(usize, usize)> {
+        return &self.cells;
+    }
+
+    // Position of a cell in `active_cells`.
+    pub fn cell_index(
+        &self,
+        x: usize,
+        y: usize
+    ) -> Option<usize> {
+        return self.index[x * self.size.1 + y];
+    }
+
+    // Groups of active cells connected through their sides.
+    pub fn regions(&self) -> Vec<Vec<(usize, usize)>> {
+
+        let (size_x, size_y) = self.size;
+        let mut seen: Vec<bool> = vec![false; size_x * size_y];
+        let mut regions: Vec<Vec<(usize, usize)>> = Vec::new();
+        for &start in self.cells.iter() {
+            if seen[start.0 * size_y + start.1] {
+                continue;
+            }
+            let mut region: Vec<(usize, usize)> = Vec::new();
+            let mut queue: VecDeque<(usize, usize)> = VecDeque::new();
+            seen[start.0 * size_y + start.1] = true;
+            queue.push_back(start);
+            while let Some((x, y)) = queue.pop_front() {
+                region.push((x, y));
+                let next = [
+                    (x + 1, y),
+                    (x.wrapping_sub(1), y),
+                    (x, y + 1),
+                    (x, y.wrapping_sub(1))
+                ];
+                for &(nx, ny) in next.iter() {
+                    if nx < size_x && ny < size_y && self.is_active(nx, ny)
+                        && !seen[nx * size_y + ny] {
+                        seen[nx * size_y + ny] = true;
+                        queue.push_back((nx, ny));
+                    }
+                }
+            }
+            regions.push(region);
+        }
+        return regions;
+
+    }
+
+    // True for a mask without active cells too, as there is nothing to
+    // connect.
+    pub fn is_connected(&self) -> bool {
+        return self.regions().len() <= 1;
+    }
+
+    // The same mask with only its largest region left active. Mazes can
+    // only be generated on connected masks.
+    pub fn largest_region(&self) -> MazeMask {
+
+        let mut active: Vec<bool> = vec![false; self.size.0 * self.size.1];
+        if let Some(region) = self.regions().iter().max_by_key(|r| r.len()) {
+            for &(x, y) in region.iter() {
+                active[x * self.size.1 + y] = true;
+            }
+        }
+        return MazeMask::new(self.size.0, self.size.1, active);
+
+    }
+
+    // Parse an ASCII template.
+    pub fn from_text(
+        text: &str
+    ) -> Result<MazeMask, Error> {
+
+        let mut lines: Vec<Vec<char>> = text.lines()
+            .map(|line| line.chars().collect())
+            .collect();
+        while lines.last().map_or(false, |l| l.iter().all(|c| c.is_whitespace())) {
+            lines.pop();
+        }
+        let size_x = lines.len();
+        let size_y = lines.iter().map(|l| l.len()).max().unwrap_or(0);
+
+        let mut active: Vec<bool> = vec![false; size_x * size_y];
+        for (x, line) in lines.iter().enumerate() {
+            for (y, c) in line.iter().enumerate() {
+                active[x * size_y + y] = !c.is_whitespace() && *c != '.';
+            }
+        }
+        return MazeMask::checked(size_x, size_y, active);
+
+    }
+
+    // Parse a plain (P1) or raw (P4) PBM image.
+    pub fn from_pbm(
+        bytes: &[u8]
+    ) -> Result<MazeMask, Error> {
+
+        let mut pos: usize = 0;
+        let magic = pbm_token(bytes, &mut pos).ok_or(Error::BadFormat)?;
+        let raw = match magic {
+            b"P1" => false,
+            b"P4" => true,
+            _ => return Err(Error::BadFormat)
+        };
+        let width = pbm_number(bytes, &mut pos)?;
+        let height = pbm_number(bytes, &mut pos)?;
+
+        // Image rows run along x. The pixels must all be there before
+        // any room is made for them
+        let count = width.checked_mul(height).ok_or(Error::BadFormat)?;
+        let mut active: Vec<bool> = Vec::new();
+        if raw {
+            // A single blank separates the header from the pixels
+            let data = bytes.get(pos + 1..).ok_or(Error::BadFormat)?;
+            let row_len = (width + 7) / 8;
+            if row_len.checked_mul(height).map_or(true, |len| data.len() < len) {
+                return Err(Error::BadFormat);
+            }
+            active.reserve(count);
+            for x in 0..height {
+                for y in 0..width {
+                    active.push(data[x * row_len + y / 8] >> (7 - y % 8) & 1 == 1);
+                }
+            }
+        } else {
+            for b in bytes[pos..].iter() {
+                match b {
+                    b'0' => active.push(false),
+                    b'1' => active.push(true),
+                    _ => {}
+                }
+            }
+            if active.len() < count {
+                return Err(Error::BadFormat);
+            }
+            active.truncate(count);
+        }
+        return MazeMask::checked(height, width, active);
+
+    }
+
+    // Decode a PNG image. Pixels darker than middle gray are active.
+    pub fn from_png<R: Read>(
+        reader: R
+    ) -> Result<MazeMask, Error> {
+
+        let mut decoder = png::Decoder::new(reader);
+        decoder.set_transformations(
+            png::Transformations::EXPAND | png::Transformations::STRIP_16
+        );
+        let (info, mut reader) = decoder.read_info()?;
+        let mut buf: Vec<u8> = vec![0; info.buffer_size()];
+        reader.next_frame(&mut buf)?;
+
+        let channels: usize = match info.color_type {
+            png::ColorType::Grayscale => 1,
+            png::ColorType::GrayscaleAlpha => 2,
+            png::ColorType::RGB => 3,
+            png::ColorType::RGBA => 4,
+            png::ColorType::Indexed => return Err(Error::BadFormat)
+        };
+        let (width, height) = (info.width as usize, info.height as usize);
+        let count = width.checked_mul(height).ok_or(Error::BadFormat)?;
+        if height.checked_mul(info.line_size).map_or(true, |len| buf.len() < len)
+            || width.checked_mul(channels).map_or(true, |len| info.line_size < len)
+        {
+            return Err(Error::BadFormat);
+        }
+        let mut active: Vec<bool> = Vec::with_capacity(count);
+        for x in 0..height {
+            let row = &buf[x * info.line_size..];
+            for y in 0..width {
+                let px = &row[y * channels..(y + 1) * channels];
+                let (light, alpha): (u32, u8) = match channels {
+                    1 => (px[0] as u32, 255),
+                    2 => (px[0] as u32, px[1]),
+                    3 => ((px[0] as u32 + px[1] as u32 + px[2] as u32) / 3, 255),
+                    _ => ((px[0] as u32 + px[1] as u32 + px[2] as u32) / 3, px[3])
+                };
+                active.push(light < 128 && alpha >= 128);
+            }
+        }
+        return MazeMask::checked(height, width, active);
+
+    }
+
+    // Load a mask. ".png" and ".pbm" files are images, anything else is
+    // read as an ASCII template.
+    pub fn load<P: AsRef<std::path::Path>>(
+        path: P
+    ) -> Result<MazeMask, Error> {
+
+        let mut file = std::fs::File::open(&path)?;
+        match path.as_ref().extension().and_then(|e| e.to_str()) {
+            Some("png") => {
+                return MazeMask::from_png(file);
+            }
+            Some("pbm") => {
+                let mut bytes: Vec<u8> = Vec::new();
+                file.read_to_end(&mut bytes)?;
+                return MazeMask::from_pbm(&bytes);
+            }
+            _ => {
+                let mut text = String::new();
+                file.read_to_string(&mut text)?;
+                return MazeMask::from_text(&text);
+            }
+        }
+
+    }
+
+    // Mask with at least one active cell.
+    fn checked(
+        size_x: usize,
+        size_y: usize,
+        active: Vec<bool>
+    ) -> Result<MazeMask, Error> {
+
+        if !active.iter().any(|a| *a) {
+            return Err(Error::Empty);
+        }
+        return Ok(MazeMask::new(size_x, size_y, active));
+
+    }
+
+}
@@ -1,14 +1,1054 @@
 use crate::renderer;
 use crate::renderer::mesh::*;
 use crate::mazegen::*;
+use crate::mazesolve::UNREACHABLE;
+use crate::mazegrid::*;
+use crate::maze3::*;
 
-pub fn cell2(
+// Height of the deck of a bridge over a crossing of a weave maze.
+pub const BRIDGE_HEIGHT: f32 = 0.5;
+
+// Wall face standing on the segment from `p` to `q`, between z = `lo`
+// and z = `hi`, visible from the right of the segment, in a colour.
+fn coloured_face(
+    tris: &mut Vec<Triangle>,
+    p: (f32, f32),
+    q: (f32, f32),
+    lo: f32,
+    hi: f32,
+    colour: (f32, f32, f32)
+) {
+
+    let (r, g, b) = colour;
+    tris.push(
+        raw_triangle!(
+            pos: p.0, p.1, hi; col: r, g, b;
+            pos: p.0, p.1, lo; col: r, g, b;
+            pos: q.0, q.1, lo; col: r, g, b
+        )
+    );
+    tris.push(
+        raw_triangle!(
+            pos: p.0, p.1, hi; col: r, g, b;
+            pos: q.0, q.1, lo; col: r, g, b;
+            pos: q.0, q.1, hi; col: r, g, b
+        )
+    );
+
+}
+
+// Wall face like `coloured_face` up to `bevel` below `hi`, from where a
+// slope in `top_colour` leans back by `bevel` into the wall. `turns`
+// tell how the outline of the wall goes on past `p` and `q`: -1 round
+// a corner of the wall, 1 round a corner of the passage, 0 straight.
+// The slope ends are cut at 45 degrees so that the slopes of faces
+// meeting at a corner close it.
+fn bevelled_face(
+    tris: &mut Vec<Triangle>,
+    p: (f32, f32),
+    q: (f32, f32),
+    hi: f32,
+    bevel: f32,
+    turns: (f32, f32),
+    colour: (f32, f32, f32),
+    top_colour: (f32, f32, f32)
+) {
+
+    let len = ((q.0 - p.0).powi(2) + (q.1 - p.1).powi(2)).sqrt();
+    if len == 0.0 {
+        return;
+    }
+    if bevel <= 0.0 {
+        coloured_face(tris, p, q, 0.0, hi, colour);
+        return;
+    }
+    let mid = hi - bevel;
+    coloured_face(tris, p, q, 0.0, mid, colour);
+
+    // Along the face, and back into the wall
+    let d = ((q.0 - p.0) / len * bevel, (q.1 - p.1) / len * bevel);
+    let n = (-d.1, d.0);
+    let top_p = (p.0 + n.0 - d.0 * turns.0, p.1 + n.1 - d.1 * turns.0);
+    let top_q = (q.0 + n.0 + d.0 * turns.1, q.1 + n.1 + d.1 * turns.1);
+    let (r, g, b) = top_colour;
+    tris.push(
+        raw_triangle!(
+            pos: top_p.0, top_p.1, hi; col: r, g, b;
+            pos: p.0, p.1, mid; col: r, g, b;
+            pos: q.0, q.1, mid; col: r, g, b
+        )
+    );
+    tris.push(
+        raw_triangle!(
+            pos: top_p.0, top_p.1, hi; col: r, g, b;
+            pos: q.0, q.1, mid; col: r, g, b;
+            pos: top_q.0, top_q.1, hi; col: r, g, b
+        )
+    );
+
+}
+
+// Way a face from `p` to `q` looks, see `coloured_face`.
+fn facing(
+    p: (f32, f32),
+    q: (f32, f32)
+) -> Direction {
+
+    let (dx, dy) = (q.0 - p.0, q.1 - p.1);
+    return if dy.abs() > dx.abs() {
+        if dy > 0.0 { Direction::PosX } else { Direction::NegX }
+    } else {
+        if dx < 0.0 { Direction::PosY } else { Direction::NegY }
+    };
+
+}
+
+// Multiply two colours.
+fn mix(
+    a: (f32, f32, f32),
+    b: (f32, f32, f32)
+) -> (f32, f32, f32) {
+    return (a.0 * b.0, a.1 * b.1, a.2 * b.2);
+}
+
+// Colour of the doors and key of a lock, cycling through a palette.
+pub fn key_colour(
+    colour: usize
+) -> (f32, f32, f32) {
+
+    let palette: [(f32, f32, f32); 6] = [
+        (0.9, 0.2, 0.2),
+        (0.9, 0.8, 0.1),
+        (0.2, 0.8, 0.3),
+        (0.2, 0.4, 0.9),
+        (0.8, 0.3, 0.8),
+        (0.9, 0.5, 0.1)
+    ];
+    return palette[colour % palette.len()];
+
+}
+
+// Locked door across the passage leaving the cell with its corner at
+// (`x`, `y`) through `dir`, seen from both sides. It fills the passage
+// from the floor up to `height`.
+pub fn door(
     msdata: &mut MeshData,
+    x: f32,
+    y: f32,
+    dir: u8,
+    thiccness: f32,
+    height: f32,
+    colour: (f32, f32, f32)
+) -> Mesh {
+
+    let (a, c) = (1.0 - thiccness, thiccness);
+    let (p, q) = match dir {
+        0 => ((x + 1.0, y + c), (x + 1.0, y + a)),
+        1 => ((x, y + c), (x, y + a)),
+        2 => ((x + c, y + 1.0), (x + a, y + 1.0)),
+        _ => ((x + c, y), (x + a, y))
+    };
+    let mut tris: Vec<Triangle> = Vec::new();
+    coloured_face(&mut tris, p, q, 0.0, height, colour);
+    coloured_face(&mut tris, q, p, 0.0, height, colour);
+    return Mesh::new(msdata, &mut tris);
+
+}
+
+// Inner walls of a crossing of a weave maze. The passage along axis
+// `over` runs on a bridge across the cell, walled above the deck; the
+// other runs through a tunnel below it. Both are open at the sides the
+// other one passes through. Walls stand on the floor at z = 0 and have
+// the height and colours of `style`.
+pub fn crossing_tris(
+    x: usize,
+    y: usize,
+    over: u8,
+    style: &MazeStyle
+) -> Vec<Triangle> {
+
+    let mut tris: Vec<Triangle> = Vec::new();
+    let (x, y) = (x as f32, y as f32);
+    let thiccness = style.wall_thickness / 2.0;
+    let (a, c, d) = (1.0 - thiccness, thiccness, BRIDGE_HEIGHT);
+    let mut face = |p: (f32, f32), q: (f32, f32), lo: f32, hi: f32| {
+        coloured_face(&mut tris, p, q, lo, hi, style.face_colours[facing(p, q).as_u8() as usize]);
+    };
+
+    // Sides along x and along y of the square in the middle, seen from
+    // both sides as the passages look at them from in and outside
+    let along_x = [((x + a, y + c), (x + c, y + c)), ((x + c, y + a), (x + a, y + a))];
+    let along_y = [((x + c, y + c), (x + c, y + a)), ((x + a, y + a), (x + a, y + c))];
+    let (bridge, tunnel) = if over == 0 { (along_x, along_y) } else { (along_y, along_x) };
+    for &(p, q) in bridge.iter() {
+        face(p, q, d, style.wall_height);
+        face(q, p, d, style.wall_height);
+    }
+    for &(p, q) in tunnel.iter() {
+        face(p, q, 0.0, d);
+        face(q, p, 0.0, d);
+    }
+    let (from, to) = if over == 0 {
+        ((x, y + c), (x + 1.0, y + a))
+    } else {
+        ((x + c, y), (x + a, y + 1.0))
+    };
+    flat_quad(&mut tris, from, to, d, style.floor_colour, true);
+    flat_quad(&mut tris, from, to, d, style.floor_colour, false);
+    return tris;
+
+}
+
+pub fn diamond(
+    msdata: &mut MeshData,
+    pos_x: f32,
+    pos_y: f32,
+    pos_z: f32,
+    scale: f32
+) -> Mesh {
+    return gem(msdata, pos_x, pos_y, pos_z, scale, (0.0, 1.0, 1.0));
+}
+
+// Octahedron like `diamond`, in a colour.
+pub fn gem(
+    msdata: &mut MeshData,
+    pos_x: f32,
+    pos_y: f32,
+    pos_z: f32,
+    scale: f32,
+    colour: (f32, f32, f32)
+) -> Mesh {
+
+    let x = pos_x;
+    let y = pos_y;
+    let z = pos_z;
+    let s = scale;
+    let (r, g, b) = colour;
+
+    let mut tris: Vec<Triangle> = vec![
+        raw_triangle!(
+            pos: x, y, z+s; col: 0.8*r, 0.8*g, 0.8*b;
+            pos: x+s, y, z; col: 0.8*r, 0.8*g, 0.8*b;
+            pos: x, y+s, z; col: 0.8*r, 0.8*g, 0.8*b
+        ),
+        raw_triangle!(
+            pos: x, y, z+s; col: 0.7*r, 0.7*g, 0.7*b;
+            pos: x, y+s, z; col: 0.7*r, 0.7*g, 0.7*b;
+            pos: x-s, y, z; col: 0.7*r, 0.7*g, 0.7*b
+        ),
+        raw_triangle!(
+            pos: x, y, z+s; col: 0.8*r, 0.8*g, 0.8*b;
+            pos: x-s, y, z; col: 0.8*r, 0.8*g, 0.8*b;
+            pos: x, y-s, z; col: 0.8*r, 0.8*g, 0.8*b
+        ),
+        raw_triangle!(
+            pos: x, y, z+s; col: 0.7*r, 0.7*g, 0.7*b;
+            pos: x, y-s, z; col: 0.7*r, 0.7*g, 0.7*b;
+            pos: x+s, y, z; col: 0.7*r, 0.7*g, 0.7*b
+        ),
+        raw_triangle!(
+            pos: x, y+s, z; col: 0.7*r, 0.7*g, 0.7*b;
+            pos: x+s, y, z; col: 0.7*r, 0.7*g, 0.7*b;
+            pos: x, y, z-s; col: 0.7*r, 0.7*g, 0.7*b
+        ),
+        raw_triangle!(
+            pos: x-s, y, z; col: 0.6*r, 0.6*g, 0.6*b;
+            pos: x, y+s, z; col: 0.6*r, 0.6*g, 0.6*b;
+            pos: x, y, z-s; col: 0.6*r, 0.6*g, 0.6*b
+        ),
+        raw_triangle!(
+            pos: x, y-s, z; col: 0.7*r, 0.7*g, 0.7*b;
+            pos: x-s, y, z; col: 0.7*r, 0.7*g, 0.7*b;
+            pos: x, y, z-s; col: 0.7*r, 0.7*g, 0.7*b
+        ),
+        raw_triangle!(
+            pos: x+s, y, z; col: 0.6*r, 0.6*g, 0.6*b;
+            pos: x, y-s, z; col: 0.6*r, 0.6*g, 0.6*b;
+            pos: x, y, z-s; col: 0.6*r, 0.6*g, 0.6*b
+        )
+    ];
+
+    return Mesh::new(msdata, &mut tris);
+
+}
+
+// Tints every cell's walls and floor by how far it is from a cell,
+// walking through the maze, as a "heat" cue.
+#[derive(Debug, Clone)]
+pub struct DistanceColours {
+    pub from: (usize, usize),
+    pub gradient: Vec<(f32, f32, f32)>  // From `from` to the farthest cell, evenly spaced
+}
+
+impl DistanceColours {
+
+    // Warm near the cell, cool far away.
+    pub fn new(
+        from: (usize, usize)
+    ) -> DistanceColours {
+        return DistanceColours {
+            from: from,
+            gradient: vec![(1.0, 0.8, 0.6), (1.0, 1.0, 0.9), (0.7, 0.85, 1.0)]
+        };
+    }
+
+    // Colour of every cell, indexed [x][y]. Cells that cannot be reached
+    // are not tinted.
+    pub fn cell_colours(
+        &self,
+        maze: &Maze2
+    ) -> Vec<Vec<(f32, f32, f32)>> {
+
+        let dists = maze.distance_map(self.from);
+        let max = dists.iter().filter(|&&d| d != UNREACHABLE).max().copied().unwrap_or(0).max(1);
+        let size = maze.get_size();
+        return (0..size.0)
+            .map(|x| {
+                (0..size.1)
+                    .map(|y| match dists[maze.index(x, y)] {
+                        UNREACHABLE => (1.0, 1.0, 1.0),
+                        d => self.colour_at(d as f32 / max as f32)
+                    })
+                    .collect()
+            })
+            .collect();
+
+    }
+
+    // Colour of the gradient at `t`, from 0.0 to 1.0.
+    pub fn colour_at(
+        &self,
+        t: f32
+    ) -> (f32, f32, f32) {
+
+        if self.gradient.len() < 2 {
+            return self.gradient.first().copied().unwrap_or((1.0, 1.0, 1.0));
+        }
+        let pos = t.max(0.0).min(1.0) * (self.gradient.len() - 1) as f32;
+        let i = (pos as usize).min(self.gradient.len() - 2);
+        let f = pos - i as f32;
+        let (p, q) = (self.gradient[i], self.gradient[i + 1]);
+        return (p.0 + (q.0 - p.0) * f, p.1 + (q.1 - p.1) * f, p.2 + (q.2 - p.2) * f);
+
+    }
+
+}
+
+// Seen from above, the maze as a grid of blocks, two per cell along
+// each axis: the wall band on the low side of the cell, then its inside.
+// Insides are open, bands where a passage leads through them and the
+// posts where bands meet only inside rooms.
+struct BlockGrid<'a> {
+    maze: &'a Maze2,
+    style: &'a MazeStyle,
+    blocks: (usize, usize),
+    thiccness: f32
+}
+
+impl<'a> BlockGrid<'a> {
+
+    // Wrapping axes end before the last band, which is the first one of
+    // the next copy.
+    fn new(
+        maze: &'a Maze2,
+        style: &'a MazeStyle
+    ) -> BlockGrid<'a> {
+
+        let size = maze.get_size();
+        let wrap = maze.get_wrap();
+        return BlockGrid {
+            maze: maze,
+            style: style,
+            blocks: (2 * size.0 + !wrap.0 as usize, 2 * size.1 + !wrap.1 as usize),
+            thiccness: style.wall_thickness / 2.0
+        };
+
+    }
+
+    // Where a block starts along an axis.
+    fn start(
+        &self,
+        block: usize
+    ) -> f32 {
+
+        let line = (block / 2) as f32;
+        return if block % 2 == 0 { line - self.thiccness } else { line + self.thiccness };
+
+    }
+
+    // Cell a block belongs to along an axis of `len` cells: its own, or
+    // the one before the band.
+    fn cell(
+        block: usize,
+        len: usize
+    ) -> usize {
+        return (block + 2 * len - 1) / 2 % len;
+    }
+
+    // Blocks one past the end are the first ones on wrapping axes and
+    // solid otherwise. The first and last bands of axes that do not wrap
+    // are the perimeter, solid whatever the cells next to them say.
+    fn is_open(
+        &self,
+        block: (usize, usize)
+    ) -> bool {
+
+        let maze = self.maze;
+        let size = maze.get_size();
+        let wrap = maze.get_wrap();
+        let u = if block.0 == self.blocks.0 && wrap.0 { 0 } else { block.0 };
+        let v = if block.1 == self.blocks.1 && wrap.1 { 0 } else { block.1 };
+        if u >= self.blocks.0 || v >= self.blocks.1 {
+            return false;
+        }
+        if (u == 0 && !wrap.0) || (v == 0 && !wrap.1) {
+            return false;
+        }
+        let (x, y) = (u / 2, v / 2);
+        return match (u % 2, v % 2) {
+            (1, 1) => maze.is_cell_active(x, y),
+            (0, 1) => x < size.0 && maze.is_cell_open(x, y, 1),
+            (1, 0) => y < size.1 && maze.is_cell_open(x, y, 3),
+            _ => {
+                if x == 0 || y == 0 || x == size.0 || y == size.1 {
+                    return false;
+                }
+                let room = maze.room_at(x, y);
+                room.is_some() && [(x - 1, y - 1), (x - 1, y), (x, y - 1)].iter()
+                    .all(|&(i, j)| maze.room_at(i, j) == room)
+            }
+        };
+
+    }
+
+    // Corners of the area the blocks cover.
+    fn extent(&self) -> ((f32, f32), (f32, f32)) {
+        return (
+            (self.start(0), self.start(0)),
+            (self.start(self.blocks.0), self.start(self.blocks.1))
+        );
+    }
+
+    // Brightness of a solid block, the same on all its faces and varying
+    // from block to block by up to the jitter of the style either way.
+    // Fixed by the seed of the maze.
+    fn jitter(
+        &self,
+        block: (usize, usize)
+    ) -> f32 {
+
+        let amount = self.style.jitter;
+        if amount == 0.0 {
+            return 1.0;
+        }
+        let wrap = self.maze.get_wrap();
+        let u = if block.0 == self.blocks.0 && wrap.0 { 0 } else { block.0 };
+        let v = if block.1 == self.blocks.1 && wrap.1 { 0 } else { block.1 };
+        let mut h = self.maze.get_seed().unwrap_or(0)
+            ^ (u as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+            ^ (v as u64).wrapping_mul(0xc2b2_ae3d_27d4_eb4f);
+        h = (h ^ (h >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        h = (h ^ (h >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        h ^= h >> 31;
+        let unit = (h >> 40) as f32 / (1u64 << 24) as f32;
+        return 1.0 + amount * (2.0 * unit - 1.0);
+
+    }
+
+    // Outer faces and tops of the perimeter, so that the maze is closed
+    // off seen from outside too. Wrapping axes have no perimeter.
+    fn perimeter(
+        &self,
+        tris: &mut Vec<Triangle>
+    ) {
+
+        let style = self.style;
+        let wrap = self.maze.get_wrap();
+        let ((x0, y0), (x1, y1)) = self.extent();
+        let band = 2.0 * self.thiccness;
+        let height = style.wall_height;
+        let mut face = |p: (f32, f32), q: (f32, f32)| {
+            coloured_face(tris, p, q, 0.0, height, style.face_colours[facing(p, q).as_u8() as usize]);
+        };
+        if !wrap.0 {
+            face((x0, y1), (x0, y0));
+            face((x1, y0), (x1, y1));
+        }
+        if !wrap.1 {
+            face((x0, y0), (x1, y0));
+            face((x1, y1), (x0, y1));
+        }
+        if !wrap.0 {
+            flat_quad(tris, (x0, y0), (x0 + band, y1), height, style.top_colour, true);
+            flat_quad(tris, (x1 - band, y0), (x1, y1), height, style.top_colour, true);
+        }
+        if !wrap.1 {
+            // Tops between the ones along x, if any
+            let (x0, x1) = if wrap.0 { (x0, x1) } else { (x0 + band, x1 - band) };
+            flat_quad(tris, (x0, y0), (x1, y0 + band), height, style.top_colour, true);
+            flat_quad(tris, (x0, y1 - band), (x1, y1), height, style.top_colour, true);
+        }
+
+    }
+
+    // Posts `width` across on the solid corners of cells, where bands
+    // meet, that can be seen from some cell. Posts narrower than the
+    // walls are hidden inside them.
+    fn pillars(
+        &self,
+        tris: &mut Vec<Triangle>,
+        width: f32
+    ) {
+
+        let style = self.style;
+        let wrap = self.maze.get_wrap();
+        let (w, height) = (width / 2.0, style.wall_height);
+        // Block before another, one past the end if there is none
+        let before = |block: usize, len: usize, wraps: bool| -> usize {
+            return if block > 0 { block - 1 } else if wraps { len - 1 } else { len };
+        };
+        for u in (0..self.blocks.0).step_by(2) {
+            for v in (0..self.blocks.1).step_by(2) {
+                let (u0, v0) = (before(u, self.blocks.0, wrap.0), before(v, self.blocks.1, wrap.1));
+                let seen = [(u0, v0), (u0, v + 1), (u + 1, v0), (u + 1, v + 1)].iter()
+                    .any(|&block| self.is_open(block));
+                if self.is_open((u, v)) || !seen {
+                    continue;
+                }
+                let jitter = self.jitter((u, v));
+                let shade = (jitter, jitter, jitter);
+                let (x, y) = ((u / 2) as f32, (v / 2) as f32);
+                let (x0, y0, x1, y1) = (x - w, y - w, x + w, y + w);
+                let sides = [
+                    ((x0, y1), (x0, y0)),
+                    ((x1, y0), (x1, y1)),
+                    ((x0, y0), (x1, y0)),
+                    ((x1, y1), (x0, y1))
+                ];
+                for &(p, q) in sides.iter() {
+                    let colour = style.face_colours[facing(p, q).as_u8() as usize];
+                    coloured_face(tris, p, q, 0.0, height, mix(colour, shade));
+                }
+                flat_quad(tris, (x0, y0), (x1, y1), height, mix(style.top_colour, shade), true);
+            }
+        }
+
+    }
+
+    // Faces between open and solid blocks, standing on the floor, in the
+    // colour of the way they face. Faces in line facing the same way in
+    // the same colour are merged. `colours` tints the faces by the cell
+    // of the open block, and the jitter of the style by the solid one.
+    fn walls(
+        &self,
+        tris: &mut Vec<Triangle>,
+        colours: Option<&Vec<Vec<(f32, f32, f32)>>>
+    ) {
+
+        let style = self.style;
+        let size = self.maze.get_size();
+        let wrap = self.maze.get_wrap();
+        let bevel = style.bevel.max(0.0).min(self.thiccness).min(style.wall_height);
+        let tint = |open: (usize, usize), solid: (usize, usize)| -> (f32, f32, f32) {
+            let (x, y) = (BlockGrid::cell(open.0, size.0), BlockGrid::cell(open.1, size.1));
+            let jitter = self.jitter(solid);
+            return mix(colours.map_or((1.0, 1.0, 1.0), |c| c[x][y]), (jitter, jitter, jitter));
+        };
+
+        // Lines across x, then across y
+        for axis in 0..2 {
+            let (lines, along) = if axis == 0 { self.blocks } else { (self.blocks.1, self.blocks.0) };
+            let wraps = if axis == 0 { wrap.1 } else { wrap.0 };
+            let block = |line: usize, i: usize| -> (usize, usize) {
+                return if axis == 0 { (line, i) } else { (i, line) };
+            };
+            // How the outline goes on at block `i` past the end of a face,
+            // see `bevelled_face`
+            let turn = |line: usize, before: bool, i: usize| -> f32 {
+                let (open, solid) = if before { (line - 1, line) } else { (line, line - 1) };
+                return match (self.is_open(block(open, i)), self.is_open(block(solid, i))) {
+                    (true, false) => 0.0,
+                    (false, false) => 1.0,
+                    _ => -1.0
+                };
+            };
+            for line in 1..lines + 1 {
+                // Faces are open before the line or after it
+                let mut run: Option<(usize, bool, (f32, f32, f32))> = None;
+                for i in 0..along + 1 {
+                    let mut face: Option<(bool, (f32, f32, f32))> = None;
+                    if i < along {
+                        let (before, after) = (block(line - 1, i), block(line, i));
+                        let (open_before, open_after) = (self.is_open(before), self.is_open(after));
+                        if open_before != open_after {
+                            face = Some((open_before, if open_before {
+                                tint(before, after)
+                            } else {
+                                tint(after, before)
+                            }));
+                        }
+                    }
+                    if run.map(|(_, before, tint)| (before, tint)) == face {
+                        continue;
+                    }
+                    if let Some((start, before, tint)) = run {
+                        let at = self.start(line);
+                        let (from, to) = (self.start(start), self.start(i));
+                        let prev = if start > 0 { start - 1 } else if wraps { along - 1 } else { along + 1 };
+                        let turns = (turn(line, before, prev), turn(line, before, i));
+                        let (p, q, turns) = match (axis, before) {
+                            (0, true) => ((at, to), (at, from), (turns.1, turns.0)),
+                            (0, false) => ((at, from), (at, to), turns),
+                            (_, true) => ((from, at), (to, at), turns),
+                            (_, false) => ((to, at), (from, at), (turns.1, turns.0))
+                        };
+                        let colour = mix(style.face_colours[facing(p, q).as_u8() as usize], tint);
+                        bevelled_face(tris, p, q, style.wall_height, bevel, turns, colour, mix(style.top_colour, tint));
+                    }
+                    run = face.map(|(before, tint)| (i, before, tint));
+                }
+            }
+        }
+
+    }
+
+}
+
+// Look of a maze mesh. Walls stand on the floor at z = 0, below the
+// eyes of the player at z = 0.5. The player keeps a quarter of a cell
+// away from walls, so walls and pillars should be at most 0.5 thick.
+#[derive(Debug, Clone)]
+pub struct MazeStyle {
+    pub wall_height: f32,
+    pub wall_thickness: f32,                // Across a wall, corridors get the rest of a cell
+    pub bevel: f32,                         // Slope along the tops of walls, at most half as wide as they are
+    pub face_colours: [(f32, f32, f32); 4], // Of walls by the way they face, see `Direction`
+    pub top_colour: (f32, f32, f32),        // Of the tops and bevels of walls
+    pub floor_colour: (f32, f32, f32),
+    pub ceiling: bool,                      // Roof over the maze at the top of the walls
+    pub ceiling_colour: (f32, f32, f32),
+    pub pillars: Option<f32>,               // Posts this wide where walls meet
+    pub jitter: f32                         // Walls vary in brightness by up to this much either way
+}
+
+impl MazeStyle {
+
+    // Flat grey walls without pillars, left to the light to shade.
+    pub fn new() -> MazeStyle {
+        return MazeStyle {
+            wall_height: 1.0,
+            wall_thickness: 0.2,
+            bevel: 0.0,
+            face_colours: [(0.8, 0.8, 0.8); 4],
+            top_colour: (0.9, 0.9, 0.9),
+            floor_colour: (0.5, 0.5, 0.5),
+            ceiling: false,
+            ceiling_colour: (0.9, 0.9, 0.9),
+            pillars: None,
+            jitter: 0.0
+        };
+    }
+
+}
+
+// All walls of a maze in one mesh, see `BlockGrid`, with the floor, the
+// perimeter and what else the style asks for. Faces hidden inside the
+// walls are left out, and runs of faces along a wall are merged into
+// single quads.
+pub struct MazeMesh2 {
+    mesh: IndexedMesh,
+    style: MazeStyle
+}
+
+impl MazeMesh2 {
+    
+    pub fn new(
+        maze: &Maze2,
+        style: MazeStyle
+    ) -> MazeMesh2 {
+    
+        let mut mmesh: MazeMesh2 = MazeMesh2::empty(style);
+        mmesh.rebuild(maze, None);
+        return mmesh;
+
+    }
+
+    // Mesh without walls, to be built by `rebuild`.
+    pub fn empty(
+        style: MazeStyle
+    ) -> MazeMesh2 {
+        return MazeMesh2 {
+            mesh: IndexedMesh::new(),
+            style: style
+        };
+    }
+
+    pub fn get_style(&self) -> &MazeStyle {
+        return &self.style;
+    }
+
+    // Takes effect on the next `rebuild`.
+    pub fn set_style(
+        &mut self,
+        style: MazeStyle
+    ) {
+        self.style = style;
+    }
+
+    // Replace the mesh with that of another maze, e.g. after it was
+    // regenerated, reusing its buffers. With `colours` every cell is
+    // tinted by its distance, floors included.
+    pub fn rebuild(
+        &mut self,
+        maze: &Maze2,
+        colours: Option<&DistanceColours>
+    ) {
+
+        let style = &self.style;
+        let cell_colours = colours.map(|c| c.cell_colours(maze));
+        let grid = BlockGrid::new(maze, style);
+        let mut tris: Vec<Triangle> = Vec::new();
+        grid.walls(&mut tris, cell_colours.as_ref());
+        grid.perimeter(&mut tris);
+        if let Some(width) = style.pillars {
+            grid.pillars(&mut tris, width);
+        }
+
+        // One floor under the whole maze, or one per cell in its colour
+        let (from, to) = grid.extent();
+        if cell_colours.is_none() {
+            flat_quad(&mut tris, from, to, 0.0, style.floor_colour, true);
+        }
+        if style.ceiling {
+            flat_quad(&mut tris, from, to, style.wall_height, style.ceiling_colour, false);
+        }
+
+        // Crossings
+        let size = maze.get_size();
+        for i in 0..size.0 {
+            for j in 0..size.1 {
+                if !maze.is_cell_active(i, j) {
+                    continue;
+                }
+                let mut cell_tris = match maze.get_crossing(i, j) {
+                    Some(over) => crossing_tris(i, j, over, style),
+                    None => Vec::new()
+                };
+                if let Some(ref cell_colours) = cell_colours {
+                    let (x, y) = (i as f32, j as f32);
+                    flat_quad(&mut cell_tris, (x, y), (x + 1.0, y + 1.0), 0.0, style.floor_colour, true);
+                    tint(&mut cell_tris, cell_colours[i][j]);
+                }
+                tris.append(&mut cell_tris);
+            }
+        }
+
+        // Wrapping mazes get a copy on every side the player can see
+        // across the seam
+        self.mesh.clear();
+        for (dx, dy) in wrap_offsets(maze) {
+            let mut copy = tris.clone();
+            shift(&mut copy, dx, dy);
+            self.mesh.add_triangles(&copy);
+        }
+        self.mesh.buffer_data();
+
+    }
+
+    pub fn render(
+        &self
+    ) {
+        self.mesh.render();
+    }
+
+}
+
+// Wall slab standing on a 2D segment, with ends extended so that walls
+// meeting at an angle close the corner.
+pub fn wall_segment(
+    tris: &mut Vec<Triangle>,
+    a: (f32, f32),
+    b: (f32, f32),
+    thiccness: f32
+) {
+
+    let length = ((b.0 - a.0).powi(2) + (b.1 - a.1).powi(2)).sqrt();
+    if length == 0.0 {
+        return;
+    }
+    let t = thiccness;
+    let d = ((b.0 - a.0) / length, (b.1 - a.1) / length);
+    let n = (d.1, -d.0);
+    let (lo, hi) = (thiccness, 1.0 - thiccness);
+    let a = (a.0 - d.0 * t, a.1 - d.1 * t);
+    let b = (b.0 + d.0 * t, b.1 + d.1 * t);
+
+    // Corners of the slab outline, counter-clockwise
+    let corners = [
+        (a.0 + n.0 * t, a.1 + n.1 * t),
+        (b.0 + n.0 * t, b.1 + n.1 * t),
+        (b.0 - n.0 * t, b.1 - n.1 * t),
+        (a.0 - n.0 * t, a.1 - n.1 * t)
+    ];
+    for i in 0..4 {
+        let p = corners[i];
+        let q = corners[(i + 1) % 4];
+        // Shade faces by how much they face the y axis, like `floor_cell_walls`
+        let len = ((q.0 - p.0).powi(2) + (q.1 - p.1).powi(2)).sqrt();
+        let shade = 0.7 + 0.1 * ((q.0 - p.0) / len).abs();
+        tris.push(
+            raw_triangle!(
+                pos: p.0, p.1, hi; col: shade, shade, shade;
+                pos: p.0, p.1, lo; col: shade, shade, shade;
+                pos: q.0, q.1, lo; col: shade, shade, shade
+            )
+        );
+        tris.push(
+            raw_triangle!(
+                pos: p.0, p.1, hi; col: shade, shade, shade;
+                pos: q.0, q.1, lo; col: shade, shade, shade;
+                pos: q.0, q.1, hi; col: shade, shade, shade
+            )
+        );
+    }
+
+}
+
+// Walls of one cell of a grid maze.
+pub fn grid_cell<G: Grid>(
+    tris: &mut Vec<Triangle>,
+    maze: &GridMaze<G>,
+    cell: usize,
+    thiccness: f32
+) {
+
+    for wall in maze.cell_walls(cell) {
+        for segment in wall.windows(2) {
+            wall_segment(tris, segment[0], segment[1], thiccness);
+        }
+    }
+
+}
+
+// All walls of a maze on any grid in one mesh, on a floor under the
+// whole maze.
+pub struct GridMazeMesh {
+    mesh: IndexedMesh
+}
+
+impl GridMazeMesh {
+
+    pub fn new<G: Grid>(
+        maze: &GridMaze<G>
+    ) -> GridMazeMesh {
+
+        let mut tris: Vec<Triangle> = Vec::new();
+        for cell in 0..maze.borrow_grid().cell_count() {
+            grid_cell(&mut tris, maze, cell, 0.1);
+        }
+
+        // Floor under the whole maze
+        let mut min = (std::f32::MAX, std::f32::MAX);
+        let mut max = (std::f32::MIN, std::f32::MIN);
+        for point in maze.walls().iter().flat_map(|w| w.iter()) {
+            min = (min.0.min(point.0), min.1.min(point.1));
+            max = (max.0.max(point.0), max.1.max(point.1));
+        }
+        if min.0 <= max.0 {
+            flat_quad(&mut tris, min, max, 0.0, (0.5, 0.5, 0.5), true);
+        }
+
+        let mut mesh = IndexedMesh::new();
+        mesh.add_triangles(&tris);
+        mesh.buffer_data();
+        return GridMazeMesh {
+            mesh: mesh
+        };
+
+    }
+
+    pub fn render(
+        &self
+    ) {
+        self.mesh.render();
+    }
+
+}
+
+// Offsets of the copies of a wrapping maze, including the maze itself
+// at (0, 0).
+pub fn wrap_offsets(
+    maze: &Maze2
+) -> Vec<(f32, f32)> {
+
+    let size = maze.get_size();
+    let wrap = maze.get_wrap();
+    let copies = |len: usize, wraps: bool| -> Vec<f32> {
+        return if wraps { vec![-(len as f32), 0.0, len as f32] } else { vec![0.0] };
+    };
+    let mut offsets: Vec<(f32, f32)> = Vec::new();
+    for &dx in copies(size.0, wrap.0).iter() {
+        for &dy in copies(size.1, wrap.1).iter() {
+            offsets.push((dx, dy));
+        }
+    }
+    return offsets;
+
+}
+
+// Move triangles along the floor by (`dx`, `dy`).
+fn shift(
+    tris: &mut Vec<Triangle>,
+    dx: f32,
+    dy: f32
+) {
+    for tri in tris.iter_mut() {
+        (tri.0).pos.0 += dx;
+        (tri.1).pos.0 += dx;
+        (tri.2).pos.0 += dx;
+        (tri.0).pos.1 += dy;
+        (tri.1).pos.1 += dy;
+        (tri.2).pos.1 += dy;
+    }
+}
+
+// Multiply the colour of triangles by `colour`.
+fn tint(
+    tris: &mut Vec<Triangle>,
+    colour: (f32, f32, f32)
+) {
+    for tri in tris.iter_mut() {
+        for vertex in [&mut tri.0, &mut tri.1, &mut tri.2].iter_mut() {
+            vertex.col.0 *= colour.0;
+            vertex.col.1 *= colour.1;
+            vertex.col.2 *= colour.2;
+        }
+    }
+}
+
+// Move triangles up by `dz`.
+fn raise(
+    tris: &mut Vec<Triangle>,
+    dz: f32
+) {
+    for tri in tris.iter_mut() {
+        (tri.0).pos.2 += dz;
+        (tri.1).pos.2 += dz;
+        (tri.2).pos.2 += dz;
+    }
+}
+
+// Horizontal quad, visible from above and below.
+fn floor_quad(
+    tris: &mut Vec<Triangle>,
+    from: (f32, f32),
+    to: (f32, f32),
+    z: f32,
+    shade: f32
+) {
+    flat_quad(tris, from, to, z, (shade, shade, shade), true);
+    flat_quad(tris, from, to, z, (shade, shade, shade), false);
+}
+
+// Horizontal quad in a colour, visible from above if `up` is set and
+// from below otherwise.
+fn flat_quad(
+    tris: &mut Vec<Triangle>,
+    from: (f32, f32),
+    to: (f32, f32),
+    z: f32,
+    colour: (f32, f32, f32),
+    up: bool
+) {
+
+    let (x0, y0, x1, y1) = (from.0, from.1, to.0, to.1);
+    let (r, g, b) = colour;
+    if up {
+        tris.push(
+            raw_triangle!(
+                pos: x0, y0, z; col: r, g, b;
+                pos: x1, y0, z; col: r, g, b;
+                pos: x1, y1, z; col: r, g, b
+            )
+        );
+        tris.push(
+            raw_triangle!(
+                pos: x0, y0, z; col: r, g, b;
+                pos: x1, y1, z; col: r, g, b;
+                pos: x0, y1, z; col: r, g, b
+            )
+        );
+    } else {
+        tris.push(
+            raw_triangle!(
+                pos: x0, y0, z; col: r, g, b;
+                pos: x1, y1, z; col: r, g, b;
+                pos: x1, y0, z; col: r, g, b
+            )
+        );
+        tris.push(
+            raw_triangle!(
+                pos: x0, y0, z; col: r, g, b;
+                pos: x0, y1, z; col: r, g, b;
+                pos: x1, y1, z; col: r, g, b
+            )
+        );
+    }
+
+}
+
+// Ramp rising one floor along +x through a cell, visible from above and
+// below.
+pub fn stair_ramp(
+    tris: &mut Vec<Triangle>,
+    x: usize,
+    y: usize,
+    z: usize,
+    thiccness: f32
+) {
+
+    let (x0, x1) = (x as f32, x as f32 + 1.0);
+    let (y0, y1) = (y as f32 + thiccness, y as f32 + 1.0 - thiccness);
+    let (z0, z1) = (z as f32, z as f32 + 1.0);
+    tris.push(
+        raw_triangle!(
+            pos: x0, y0, z0; col: 0.6, 0.6, 0.6;
+            pos: x1, y0, z1; col: 0.6, 0.6, 0.6;
+            pos: x1, y1, z1; col: 0.6, 0.6, 0.6
+        )
+    );
+    tris.push(
+        raw_triangle!(
+            pos: x0, y0, z0; col: 0.6, 0.6, 0.6;
+            pos: x1, y1, z1; col: 0.6, 0.6, 0.6;
+            pos: x0, y1, z0; col: 0.6, 0.6, 0.6
+        )
+    );
+    tris.push(
+        raw_triangle!(
+            pos: x0, y0, z0; col: 0.5, 0.5, 0.5;
+            pos: x1, y1, z1; col: 0.5, 0.5, 0.5;
+            pos: x1, y0, z1; col: 0.5, 0.5, 0.5
+        )
+    );
+    tris.push(
+        raw_triangle!(
+            pos: x0, y0, z0; col: 0.5, 0.5, 0.5;
+            pos: x0, y1, z0; col: 0.5, 0.5, 0.5;
+            pos: x1, y1, z1; col: 0.5, 0.5, 0.5
+        )
+    );
+
+}
+
+// Wall triangles of a cell of one floor, standing between z = 0 and
+// z = 1.
+fn floor_cell_walls(
     x: usize,
     y: usize,
     dirs: u8,
     thiccness: f32
-) -> Mesh {
+) -> Vec<Triangle> {
     let x: f32 = x as f32;
     let y: f32 = y as f32;
     let a: f32 = 1.0 - thiccness;
@@ -142,106 +1182,73 @@ pub fn cell2(
         );
     }
 
-    return Mesh::new(msdata, &mut tris);
+    return tris;
 
 }
 
-pub fn diamond(
-    msdata: &mut MeshData,
-    pos_x: f32,
-    pos_y: f32,
-    pos_z: f32,
-    scale: f32
-) -> Mesh {
-
-    let x = pos_x;
-    let y = pos_y;
-    let z = pos_z;
-    let s = scale;
-
-    let mut tris: Vec<Triangle> = vec![
-        raw_triangle!(
-            pos: x, y, z+s; col: 0.0, 0.8, 0.8;
-            pos: x+s, y, z; col: 0.0, 0.8, 0.8;
-            pos: x, y+s, z; col: 0.0, 0.8, 0.8
-        ),
-        raw_triangle!(
-            pos: x, y, z+s; col: 0.0, 0.7, 0.7;
-            pos: x, y+s, z; col: 0.0, 0.7, 0.7;
-            pos: x-s, y, z; col: 0.0, 0.7, 0.7
-        ),
-        raw_triangle!(
-            pos: x, y, z+s; col: 0.0, 0.8, 0.8;
-            pos: x-s, y, z; col: 0.0, 0.8, 0.8;
-            pos: x, y-s, z; col: 0.0, 0.8, 0.8
-        ),
-        raw_triangle!(
-            pos: x, y, z+s; col: 0.0, 0.7, 0.7;
-            pos: x, y-s, z; col: 0.0, 0.7, 0.7;
-            pos: x+s, y, z; col: 0.0, 0.7, 0.7
-        ),
-        raw_triangle!(
-            pos: x, y+s, z; col: 0.0, 0.7, 0.7;
-            pos: x+s, y, z; col: 0.0, 0.7, 0.7;
-            pos: x, y, z-s; col: 0.0, 0.7, 0.7
-        ),
-        raw_triangle!(
-            pos: x-s, y, z; col: 0.0, 0.6, 0.6;
-            pos: x, y+s, z; col: 0.0, 0.6, 0.6;
-            pos: x, y, z-s; col: 0.0, 0.6, 0.6
-        ),
-        raw_triangle!(
-            pos: x, y-s, z; col: 0.0, 0.7, 0.7;
-            pos: x-s, y, z; col: 0.0, 0.7, 0.7;
-            pos: x, y, z-s; col: 0.0, 0.7, 0.7
-        ),
-        raw_triangle!(
-            pos: x+s, y, z; col: 0.0, 0.6, 0.6;
-            pos: x, y-s, z; col: 0.0, 0.6, 0.6;
-            pos: x, y, z-s; col: 0.0, 0.6, 0.6
-        )
-    ];
+// Walls of a multi-floor maze cell, the floor separating it from the
+// cell below, and the ramp of a stairwell.
+pub fn cell3(
+    tris: &mut Vec<Triangle>,
+    maze: &Maze3,
+    x: usize,
+    y: usize,
+    z: usize,
+    thiccness: f32
+) {
 
-    return Mesh::new(msdata, &mut tris);
+    let mut cell_tris = floor_cell_walls(x, y, maze.get_cell_val(x, y, z) & 0xF, thiccness);
+    raise(&mut cell_tris, z as f32);
+    if z > 0 && !maze.is_cell_open(x, y, z, 5) {
+        floor_quad(
+            &mut cell_tris,
+            (x as f32, y as f32),
+            (x as f32 + 1.0, y as f32 + 1.0),
+            z as f32,
+            0.5
+        );
+    }
+    if maze.is_cell_open(x, y, z, 4) {
+        stair_ramp(&mut cell_tris, x, y, z, thiccness);
+    }
+    tris.append(&mut cell_tris);
 
 }
 
-pub struct MazeMesh2<'a> {
-    maze: &'a Maze2,
-    meshes: Vec<Mesh>
+// All floors of a multi-floor maze in one mesh.
+pub struct MazeMesh3 {
+    mesh: IndexedMesh
 }
 
-impl<'a> MazeMesh2<'a> {
-    
+impl MazeMesh3 {
+
     pub fn new(
-        maze: &'a Maze2,
-        msdata: &mut MeshData
-    ) -> MazeMesh2<'a> {
-    
-        let mut mmesh: MazeMesh2 = MazeMesh2 {
-            maze: maze,
-            meshes: Vec::new()
-        };
+        maze: &Maze3
+    ) -> MazeMesh3 {
+
+        let mut tris: Vec<Triangle> = Vec::new();
         let size = maze.get_size();
-        for j in 0..size.1 {
-            for i in 0..size.0 {
-                mmesh.meshes.push(
-                    cell2(msdata, i, j, maze.get_cell_val(i, j), 0.1)
-                );
+        for k in 0..size.2 {
+            for j in 0..size.1 {
+                for i in 0..size.0 {
+                    cell3(&mut tris, maze, i, j, k, 0.1);
+                }
             }
         }
-        return mmesh;
-    
+
+        let mut mesh = IndexedMesh::new();
+        mesh.add_triangles(&tris);
+        mesh.buffer_data();
+        return MazeMesh3 {
+            mesh: mesh
+        };
+
     }
 
     pub fn render(
         &self
     ) {
-        
-        for mesh in self.meshes.iter() {
-            (*mesh).render();
-        }
-
+        self.mesh.render();
     }
 
 }
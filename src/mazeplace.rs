@@ -0,0 +1,155 @@
+// Cell classification and spawn placement.
+//
+// Cells are classified by their exits. Crossings count as corridors, as
+// each of their passages runs straight through without a choice.
+
+extern crate rand;
+
+use rand::Rng;
+use crate::mazegen::*;
+use crate::mazesolve::UNREACHABLE;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CellKind {
+    DeadEnd,
+    Corridor,       // Two exits on opposite sides
+    Corner,         // Two exits at a right angle
+    TJunction,
+    Crossroads
+}
+
+// Where spawn points may go. Distances are steps through the maze.
+pub struct SpawnRules {
+    pub min_distance: usize,    // From the start, at least
+    pub min_spacing: usize,     // Between two spawn points, at least
+    pub kinds: Vec<CellKind>    // Kinds of cells allowed, any if empty
+}
+
+impl SpawnRules {
+
+    pub fn new() -> SpawnRules {
+        return SpawnRules {
+            min_distance: 0,
+            min_spacing: 1,
+            kinds: Vec::new()
+        };
+    }
+
+}
+
+impl Maze2 {
+
+    // Kind of a cell, None for cells without exits and masked out ones.
+    pub fn cell_kind(
+        &self,
+        x: usize,
+        y: usize
+    ) -> Option<CellKind> {
+
+        if !self.is_cell_active(x, y) {
+            return None;
+        }
+        if self.is_crossing(x, y) {
+            return Some(CellKind::Corridor);
+        }
+        return match self.open_count(x, y) {
+            1 => Some(CellKind::DeadEnd),
+            2 if self.is_cell_open(x, y, 0) == self.is_cell_open(x, y, 1) => {
+                Some(CellKind::Corridor)
+            }
+            2 => Some(CellKind::Corner),
+            3 => Some(CellKind::TJunction),
+            4 => Some(CellKind::Crossroads),
+            _ => None
+        };
+
+    }
+
+    // Kinds of all cells, indexed [x][y].
+    pub fn cell_kinds(&self) -> Vec<Vec<Option<CellKind>>> {
+
+        let size = self.get_size();
+        return (0..size.0)
+            .map(|x| (0..size.1).map(|y| self.cell_kind(x, y)).collect())
+            .collect();
+
+    }
+
+    // All cells of a kind, x-major.
+    pub fn cells_of_kind(
+        &self,
+        kind: CellKind
+    ) -> Vec<(usize, usize)> {
+
+        let size = self.get_size();
+        let mut cells: Vec<(usize, usize)> = Vec::new();
+        for x in 0..size.0 {
+            for y in 0..size.1 {
+                if self.cell_kind(x, y) == Some(kind) {
+                    cells.push((x, y));
+                }
+            }
+        }
+        return cells;
+
+    }
+
+    // Pick up to `count` spawn points following `rules`, fewer if not
+    // enough cells do. Each one is the allowed cell farthest from the
+    // start and the points picked before it, so they spread out over the
+    // maze; ties are broken at random. The first point is thus the
+    // allowed cell farthest from the start.
+    pub fn place_spawns<R: Rng + ?Sized>(
+        &self,
+        start: (usize, usize),
+        count: usize,
+        rules: &SpawnRules,
+        rng: &mut R
+    ) -> Vec<(usize, usize)> {
+
+        // Candidates with their distance to the nearest of the start and
+        // the points so far, and to the nearest of the points alone
+        let from_start = self.distance_map(start);
+        let size = self.get_size();
+        let mut candidates: Vec<((usize, usize), usize, usize)> = Vec::new();
+        for x in 0..size.0 {
+            for y in 0..size.1 {
+                let dist = match from_start[self.index(x, y)] {
+                    UNREACHABLE => continue,
+                    dist if dist as usize >= rules.min_distance && dist > 0 => dist as usize,
+                    _ => continue
+                };
+                let kind = self.cell_kind(x, y);
+                if rules.kinds.is_empty() || kind.map_or(false, |k| rules.kinds.contains(&k)) {
+                    candidates.push(((x, y), dist, usize::max_value()));
+                }
+            }
+        }
+        shuffle(rng, &mut candidates);
+
+        let mut spawns: Vec<(usize, usize)> = Vec::new();
+        while spawns.len() < count {
+            candidates.retain(|&(_, _, spacing)| spacing >= rules.min_spacing);
+            let best = candidates.iter()
+                .enumerate()
+                .max_by_key(|(_, &(_, nearest, _))| nearest)
+                .map(|(i, _)| i);
+            let (spawn, _, _) = match best {
+                Some(i) => candidates.swap_remove(i),
+                None => break
+            };
+            spawns.push(spawn);
+            let from_spawn = self.distance_map(spawn);
+            for (cell, nearest, spacing) in candidates.iter_mut() {
+                let dist = from_spawn[self.index(cell.0, cell.1)];
+                if dist != UNREACHABLE {
+                    *nearest = (*nearest).min(dist as usize);
+                    *spacing = (*spacing).min(dist as usize);
+                }
+            }
+        }
+        return spawns;
+
+    }
+
+}
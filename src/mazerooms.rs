@@ -0,0 +1,155 @@
+// Rooms embedded in mazes.
+//
+// Rooms are open rectangles of cells with no walls inside. They are kept
+// at least one cell apart, so every room is surrounded by corridors.
+
+extern crate rand;
+
+use rand::RngCore;
+use crate::mazegen::*;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Room {
+    pub x: usize,
+    pub y: usize,
+    pub size_x: usize,
+    pub size_y: usize
+}
+
+impl Room {
+
+    pub fn contains(
+        &self,
+        x: usize,
+        y: usize
+    ) -> bool {
+        return x >= self.x && x < self.x + self.size_x
+            && y >= self.y && y < self.y + self.size_y;
+    }
+
+    // Whether the rooms overlap or touch, leaving no cell between them.
+    pub fn is_near(
+        &self,
+        other: &Room
+    ) -> bool {
+        return self.x <= other.x + other.size_x && other.x <= self.x + self.size_x
+            && self.y <= other.y + other.size_y && other.y <= self.y + self.size_y;
+    }
+
+}
+
+// Places rooms first, then connects them with corridors generated by
+// another algorithm. Each room is entered like a single cell, so leaving
+// the rooms aside the maze has no loops.
+pub struct Rooms<G: MazeGenerator> {
+    pub inner: G,
+    pub count: usize,       // Rooms to place, fewer if they do not fit
+    pub min_size: usize,    // Cells along each side of a room
+    pub max_size: usize
+}
+
+impl<G: MazeGenerator> Rooms<G> {
+
+    fn place(
+        &self,
+        maze: &Maze2,
+        rng: &mut dyn RngCore
+    ) -> Vec<Room> {
+
+        let size = maze.get_size();
+        let mut rooms: Vec<Room> = Vec::new();
+        let min_size = self.min_size.max(1);
+        let max_size = self.max_size.max(min_size);
+        for _ in 0..self.count * 20 {
+            if rooms.len() == self.count {
+                break;
+            }
+            let size_x = min_size + rand_index(rng, max_size - min_size + 1);
+            let size_y = min_size + rand_index(rng, max_size - min_size + 1);
+            if size_x >= size.0 || size_y >= size.1 {
+                continue;
+            }
+            let room = Room {
+                x: rand_index(rng, size.0 - size_x + 1),
+                y: rand_index(rng, size.1 - size_y + 1),
+                size_x: size_x,
+                size_y: size_y
+            };
+            if rooms.iter().any(|r| r.is_near(&room)) {
+                continue;
+            }
+            let inside = (room.x..room.x + size_x)
+                .all(|x| (room.y..room.y + size_y).all(|y| maze.is_cell_active(x, y)));
+            if inside {
+                rooms.push(room);
+            }
+        }
+        return rooms;
+
+    }
+
+}
+
+impl<G: MazeGenerator> MazeGenerator for Rooms<G> {
+
+    fn generate(
+        &self,
+        maze: &mut Maze2,
+        rng: &mut dyn RngCore
+    ) {
+
+        let rooms = self.place(maze, rng);
+        self.inner.generate(maze, rng);
+        for room in rooms {
+            maze.add_room(room);
+        }
+
+        // Opening the rooms joins corridors that were apart. Keep a
+        // random spanning tree of the passages, counting each room as one
+        // cell, and wall the others up again.
+        let size = maze.get_size();
+        let node = |maze: &Maze2, x: usize, y: usize| -> usize {
+            return match maze.room_at(x, y) {
+                Some(room) => size.0 * size.1 + room,
+                None => x * size.1 + y
+            };
+        };
+        let mut passages: Vec<(usize, usize, u8)> = Vec::new();
+        for x in 0..size.0 {
+            for y in 0..size.1 {
+                for &dir in [0, 2].iter() {
+                    if !maze.is_cell_open(x, y, dir) {
+                        continue;
+                    }
+                    if let Some((nx, ny)) = maze.neighbour(x, y, dir) {
+                        if node(maze, x, y) != node(maze, nx, ny) {
+                            passages.push((x, y, dir));
+                        }
+                    }
+                }
+            }
+        }
+        shuffle(rng, &mut passages);
+
+        let mut parents: Vec<usize> = (0..size.0 * size.1 + maze.borrow_rooms().len()).collect();
+        let find = |parents: &mut Vec<usize>, mut cell: usize| -> usize {
+            while parents[cell] != cell {
+                parents[cell] = parents[parents[cell]];
+                cell = parents[cell];
+            }
+            return cell;
+        };
+        for (x, y, dir) in passages {
+            let (nx, ny) = maze.neighbour(x, y, dir).unwrap();
+            let a = find(&mut parents, node(maze, x, y));
+            let b = find(&mut parents, node(maze, nx, ny));
+            if a == b {
+                maze.wall_cell(x, y, dir);
+            } else {
+                parents[a] = b;
+            }
+        }
+
+    }
+
+}
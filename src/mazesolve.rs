@@ -0,0 +1,240 @@
+// Maze solving and distance queries.
+//
+// Searches run over cells and layers: a crossing is passed on the layer
+// of the passage it was entered by, so paths go straight through it.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use crate::mazegen::Maze2;
+
+// Distance of a cell that cannot be reached, in a distance map.
+pub const UNREACHABLE: u32 = u32::max_value();
+
+impl Maze2 {
+
+    // Cells reachable through an open passage from a cell. Crossings
+    // lead every way here, see `exits`.
+    pub fn open_neighbours(
+        &self,
+        x: usize,
+        y: usize
+    ) -> Vec<(usize, usize)> {
+
+        let mut cells: Vec<(usize, usize)> = Vec::new();
+        for dir in 0..4 {
+            if self.is_cell_open(x, y, dir) {
+                if let Some(pos) = self.neighbour(x, y, dir) {
+                    cells.push(pos);
+                }
+            }
+        }
+        return cells;
+
+    }
+
+    // Directions, cells and layers reachable in one step from a cell on
+    // a layer. Layer 0 is an ordinary cell, or a crossing with no passage
+    // chosen yet; layer 1 + axis is the passage along that axis of a
+    // crossing.
+    fn moves<'a>(
+        &'a self,
+        pos: (usize, usize),
+        layer: usize
+    ) -> impl Iterator<Item = (u8, (usize, usize), usize)> + 'a {
+
+        // Same as `exits`, without collecting the directions
+        let entered = if layer > 0 { Some(2 * (layer as u8 - 1)) } else { None };
+        let straight = move |dir: u8| entered.map_or(true, |from| dir >> 1 == from >> 1);
+        let cell = *self.borrow_cell(pos.0, pos.1);
+        let crossing = cell.get_crossing().is_some();
+        return (0..4)
+            .filter(move |&dir| cell.is_open(dir) && (!crossing || straight(dir)))
+            .filter_map(move |dir| {
+                return self.neighbour(pos.0, pos.1, dir).map(|next| {
+                    let next_layer = if self.is_crossing(next.0, next.1) {
+                        1 + (dir >> 1) as usize
+                    } else {
+                        0
+                    };
+                    return (dir, next, next_layer);
+                });
+            });
+
+    }
+
+    // Find the shortest path between two cells with A*. The path
+    // includes both ends. Returns None if `to` cannot be reached.
+    pub fn solve(
+        &self,
+        from: (usize, usize),
+        to: (usize, usize)
+    ) -> Option<Vec<(usize, usize)>> {
+
+        let size = self.get_size();
+        let wrap = self.get_wrap();
+        // Distance along one axis, which may be shorter across the seam
+        let span = |a: usize, b: usize, len: usize, wraps: bool| -> usize {
+            let d = (a as isize - b as isize).abs() as usize;
+            return if wraps { d.min(len - d) } else { d };
+        };
+        let heuristic = |pos: (usize, usize)| -> usize {
+            return span(pos.0, to.0, size.0, wrap.0) + span(pos.1, to.1, size.1, wrap.1);
+        };
+
+        // Cost and previous step of every cell and layer, indexed like
+        // the cell buffer
+        let mut costs: Vec<[u32; 3]> = vec![[UNREACHABLE; 3]; size.0 * size.1];
+        let mut came_from: Vec<[Option<(usize, usize)>; 3]> = vec![[None; 3]; size.0 * size.1];
+        let mut open: BinaryHeap<Reverse<(usize, u32, (usize, usize), usize)>> =
+            BinaryHeap::new();
+        costs[self.index(from.0, from.1)][0] = 0;
+        open.push(Reverse((heuristic(from), 0, from, 0)));
+
+        while let Some(Reverse((_, cost, pos, layer))) = open.pop() {
+            let i = self.index(pos.0, pos.1);
+            if pos == to {
+                // Walk back to the start
+                let mut path: Vec<(usize, usize)> = vec![to];
+                let (mut current, mut current_layer) = (i, layer);
+                while let Some((prev, prev_layer)) = came_from[current][current_layer] {
+                    path.push(self.position(prev));
+                    current = prev;
+                    current_layer = prev_layer;
+                }
+                path.reverse();
+                return Some(path);
+            }
+            if costs[i][layer] < cost {
+                continue;
+            }
+            for (_, next, next_layer) in self.moves(pos, layer) {
+                let next_cost = cost + 1;
+                let j = self.index(next.0, next.1);
+                if next_cost < costs[j][next_layer] {
+                    costs[j][next_layer] = next_cost;
+                    came_from[j][next_layer] = Some((i, layer));
+                    open.push(Reverse((next_cost as usize + heuristic(next), next_cost, next, next_layer)));
+                }
+            }
+        }
+
+        return None;
+
+    }
+
+    // Breadth-first distance from a cell to every other cell, indexed
+    // like the cell buffer (see `index`). Unreachable cells are
+    // UNREACHABLE. Crossings get the distance of the nearer of their two
+    // passages.
+    pub fn distance_map(
+        &self,
+        from: (usize, usize)
+    ) -> Vec<u32> {
+
+        let size = self.get_size();
+        let mut dists: Vec<u32> = vec![UNREACHABLE; size.0 * size.1];
+        dists[self.index(from.0, from.1)] = 0;
+        // Layers of crossings already reached. Other cells are reached
+        // once their distance is set, which saves a look-up per cell.
+        let mut crossings_seen: HashSet<(usize, usize)> = HashSet::new();
+
+        // One ring of cells at a time, so the distance of each ring is
+        // known without storing it with every cell
+        let mut ring: Vec<((usize, usize), usize)> = vec![(from, 0)];
+        let mut next_ring: Vec<((usize, usize), usize)> = Vec::new();
+        let mut dist: u32 = 0;
+        while !ring.is_empty() {
+            dist += 1;
+            for &(pos, layer) in ring.iter() {
+                for (_, next, next_layer) in self.moves(pos, layer) {
+                    let i = self.index(next.0, next.1);
+                    let fresh = if next_layer > 0 {
+                        crossings_seen.insert((i, next_layer))
+                    } else {
+                        dists[i] == UNREACHABLE
+                    };
+                    if fresh {
+                        dists[i] = dists[i].min(dist);
+                        next_ring.push((next, next_layer));
+                    }
+                }
+            }
+            std::mem::swap(&mut ring, &mut next_ring);
+            next_ring.clear();
+        }
+
+        return dists;
+
+    }
+
+    // Cells reachable from a cell without going through a passage that
+    // is blocked, indexed like the cell buffer (see `index`).
+    // `is_blocked` is given the cell and direction a passage is left by.
+    pub fn reachable<F: Fn(usize, usize, u8) -> bool>(
+        &self,
+        from: (usize, usize),
+        is_blocked: F
+    ) -> Vec<bool> {
+
+        let size = self.get_size();
+        let mut cells: Vec<bool> = vec![false; size.0 * size.1];
+        // Layers of crossings already reached, as in `distance_map`
+        let mut crossings_seen: HashSet<(usize, usize)> = HashSet::new();
+        let mut queue: VecDeque<((usize, usize), usize)> = VecDeque::new();
+        cells[self.index(from.0, from.1)] = true;
+        queue.push_back((from, 0));
+
+        while let Some((pos, layer)) = queue.pop_front() {
+            for (dir, next, next_layer) in self.moves(pos, layer) {
+                let i = self.index(next.0, next.1);
+                let fresh = if next_layer > 0 {
+                    !crossings_seen.contains(&(i, next_layer))
+                } else {
+                    !cells[i]
+                };
+                if fresh && !is_blocked(pos.0, pos.1, dir) {
+                    if next_layer > 0 {
+                        crossings_seen.insert((i, next_layer));
+                    }
+                    cells[i] = true;
+                    queue.push_back((next, next_layer));
+                }
+            }
+        }
+
+        return cells;
+
+    }
+
+    // Reachable cell farthest away from a cell, and its distance.
+    pub fn farthest_from(
+        &self,
+        from: (usize, usize)
+    ) -> ((usize, usize), usize) {
+
+        let dists = self.distance_map(from);
+        let mut farthest: ((usize, usize), usize) = (from, 0);
+        for (i, &dist) in dists.iter().enumerate() {
+            if dist != UNREACHABLE && dist as usize > farthest.1 {
+                farthest = (self.position(i), dist as usize);
+            }
+        }
+        return farthest;
+
+    }
+
+    // Pair of cells farthest apart (the maze diameter) and their distance.
+    // Exact for perfect mazes; a good approximation for mazes with loops.
+    pub fn farthest_pair(
+        &self
+    ) -> ((usize, usize), (usize, usize), usize) {
+
+        let (a, _) = self.farthest_from(self.first_cell());
+        let (b, dist) = self.farthest_from(a);
+        return (a, b, dist);
+
+    }
+
+}
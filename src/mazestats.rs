@@ -0,0 +1,288 @@
+// Maze statistics and difficulty metrics.
+//
+// Corridors are the runs of cells between two cells that are not plain
+// passages, i.e. dead ends and junctions. Their length is counted in
+// steps from one end to the other. Crossings are plain passages for
+// both corridors running through them.
+
+use crate::mazegen::*;
+
+#[derive(Debug, Clone)]
+pub struct MazeStats {
+    pub cells: usize,                   // Cells that are part of the maze
+    pub dead_ends: usize,
+    pub junctions: usize,               // Cells with three or more exits
+    pub corridor_lengths: Vec<usize>,   // Number of corridors by length
+    pub branching_factor: f64,          // Share of cells that are junctions
+    pub river_factor: f64,              // Mean length of dead end corridors
+    pub start: (usize, usize),
+    pub goal: (usize, usize),
+    pub solution_length: usize,         // Steps from start to goal
+    pub solution_ratio: f64,            // Share of cells on the solution
+    pub solution_turns: usize,
+    pub solution_junctions: usize,      // Junctions passed on the solution
+    pub difficulty: f64,
+}
+
+impl Maze2 {
+
+    // Follow a corridor from a cell through `dir` to the next cell that
+    // is not a plain passage. Returns that cell, the direction leading
+    // back into the corridor from it, and the length.
+    fn follow_corridor(
+        &self,
+        x: usize,
+        y: usize,
+        dir: Direction
+    ) -> ((usize, usize), Direction, usize) {
+
+        let mut pos = (x, y);
+        let mut dir = dir;
+        let mut length: usize = 0;
+        loop {
+            pos = self.next_cell(pos, dir).unwrap();
+            length += 1;
+            let back = dir.opposite();
+            if self.is_crossing(pos.0, pos.1) {
+                continue;
+            }
+            if self.open_count(pos.0, pos.1) != 2 {
+                return (pos, back, length);
+            }
+            dir = Direction::ALL.iter().copied()
+                .find(|&d| d != back && self.is_cell_open(pos.0, pos.1, d.as_u8()))
+                .unwrap();
+        }
+
+    }
+
+    // Analyse the maze. Start and goal are the two cells farthest apart.
+    pub fn stats(&self) -> MazeStats {
+
+        let size = self.get_size();
+        let cells = self.cell_count();
+
+        // Cell kinds, and corridors starting at every dead end and junction.
+        // Each corridor is found from both ends and counted from the
+        // smaller one.
+        let mut dead_ends: usize = 0;
+        let mut junctions: usize = 0;
+        let mut corridor_lengths: Vec<usize> = Vec::new();
+        let mut dead_end_corridors: usize = 0;
+        let mut dead_end_length: usize = 0;
+        for x in 0..size.0 {
+            for y in 0..size.1 {
+                if self.is_crossing(x, y) {
+                    continue;
+                }
+                let exits = self.open_count(x, y);
+                if exits == 1 {
+                    dead_ends += 1;
+                } else if exits >= 3 {
+                    junctions += 1;
+                }
+                if exits == 2 || exits == 0 {
+                    continue;
+                }
+                for &dir in Direction::ALL.iter() {
+                    if !self.is_cell_open(x, y, dir.as_u8()) {
+                        continue;
+                    }
+                    let (end, back, length) = self.follow_corridor(x, y, dir);
+                    if (x, y, dir.as_u8()) > (end.0, end.1, back.as_u8()) {
+                        continue;
+                    }
+                    if corridor_lengths.len() <= length {
+                        corridor_lengths.resize(length + 1, 0);
+                    }
+                    corridor_lengths[length] += 1;
+                    if exits == 1 || self.open_count(end.0, end.1) == 1 {
+                        dead_end_corridors += 1;
+                        dead_end_length += length;
+                    }
+                }
+            }
+        }
+
+        // Walk the solution
+        let (start, goal, solution_length) = self.farthest_pair();
+        let path = self.solve(start, goal).unwrap_or(vec![start]);
+        let mut solution_turns: usize = 0;
+        let mut solution_junctions: usize = 0;
+        for step in path.windows(3) {
+            let (a, b, c) = (step[0], step[1], step[2]);
+            if self.dir_towards(a, b) != self.dir_towards(b, c) {
+                solution_turns += 1;
+            }
+            if self.open_count(b.0, b.1) >= 3 && !self.is_crossing(b.0, b.1) {
+                solution_junctions += 1;
+            }
+        }
+
+        return MazeStats {
+            cells: cells,
+            dead_ends: dead_ends,
+            junctions: junctions,
+            corridor_lengths: corridor_lengths,
+            branching_factor: junctions as f64 / cells as f64,
+            river_factor: if dead_end_corridors > 0 {
+                dead_end_length as f64 / dead_end_corridors as f64
+            } else {
+                0.0
+            },
+            start: start,
+            goal: goal,
+            solution_length: solution_length,
+            solution_ratio: path.len() as f64 / cells as f64,
+            solution_turns: solution_turns,
+            solution_junctions: solution_junctions,
+            difficulty: difficulty(cells, solution_turns, solution_junctions),
+        };
+
+    }
+
+    // Generate mazes until one meets the target: the seeds from `seed`
+    // on are tried in turn, each with every generator, so generators
+    // differing only in their parameters (e.g. braid fraction) widen the
+    // search. Returns the seed and the index of the generator that made
+    // the maze. After `attempts` seeds without a fit the last maze tried
+    // is kept and None returned.
+    pub fn generate_to_target(
+        &mut self,
+        generators: &[&dyn MazeGenerator],
+        target: &MazeTarget,
+        seed: u64,
+        attempts: usize
+    ) -> Option<(u64, usize)> {
+
+        for attempt in 0..attempts {
+            let seed = seed.wrapping_add(attempt as u64);
+            for (i, generator) in generators.iter().enumerate() {
+                self.regenerate_using(*generator, seed);
+                if target.accepts(&self.stats()) {
+                    return Some((seed, i));
+                }
+            }
+        }
+        return None;
+
+    }
+
+}
+
+// Ranges, both ends included, the stats of a generated maze must fall
+// in, see `Maze2::generate_to_target`.
+#[derive(Debug, Clone)]
+pub struct MazeTarget {
+    pub solution_length: (usize, usize),    // Steps from start to goal
+    pub dead_end_ratio: (f64, f64),         // Share of cells that are dead ends
+    pub decision_points: (usize, usize),    // Junctions passed on the solution
+    pub difficulty: (f64, f64)
+}
+
+impl MazeTarget {
+
+    // Target every maze meets, to narrow down field by field.
+    pub fn new() -> MazeTarget {
+        return MazeTarget {
+            solution_length: (0, usize::max_value()),
+            dead_end_ratio: (0.0, 1.0),
+            decision_points: (0, usize::max_value()),
+            difficulty: (0.0, std::f64::INFINITY)
+        };
+    }
+
+    // Presets for a maze of `cells` cells. Solution length and decision
+    // points are scaled by the side of a square maze with as many cells,
+    // like the difficulty score. Easy mazes are short with few choices
+    // and many short dead ends, like most of Prim's; medium ones are
+    // like Kruskal's or Wilson's; hard ones long and winding with few
+    // dead ends, like the recursive backtracker's.
+    pub fn easy(cells: usize) -> MazeTarget {
+        let side = (cells as f64).sqrt();
+        return MazeTarget {
+            solution_length: (0, (3.5 * side) as usize),
+            dead_end_ratio: (0.3, 1.0),
+            decision_points: (0, (1.8 * side) as usize),
+            difficulty: (0.0, 1.3)
+        };
+    }
+
+    pub fn medium(cells: usize) -> MazeTarget {
+        let side = (cells as f64).sqrt();
+        return MazeTarget {
+            solution_length: ((3.0 * side) as usize, (6.0 * side) as usize),
+            dead_end_ratio: (0.2, 0.34),
+            decision_points: ((1.4 * side) as usize, (3.0 * side) as usize),
+            difficulty: (1.2, 2.0)
+        };
+    }
+
+    pub fn hard(cells: usize) -> MazeTarget {
+        let side = (cells as f64).sqrt();
+        return MazeTarget {
+            solution_length: ((6.0 * side) as usize, usize::max_value()),
+            dead_end_ratio: (0.0, 0.2),
+            decision_points: ((0.6 * side) as usize, usize::max_value()),
+            difficulty: (1.6, std::f64::INFINITY)
+        };
+    }
+
+    pub fn accepts(
+        &self,
+        stats: &MazeStats
+    ) -> bool {
+
+        let within = |value: f64, range: (f64, f64)| -> bool {
+            return value >= range.0 && value <= range.1;
+        };
+        let dead_end_ratio = stats.dead_ends as f64 / stats.cells as f64;
+        return stats.solution_length >= self.solution_length.0
+            && stats.solution_length <= self.solution_length.1
+            && stats.solution_junctions >= self.decision_points.0
+            && stats.solution_junctions <= self.decision_points.1
+            && within(dead_end_ratio, self.dead_end_ratio)
+            && within(stats.difficulty, self.difficulty);
+
+    }
+
+}
+
+// Difficulty score: choices and turns along the solution, scaled by the
+// side of a square maze with as many cells, so sizes compare fairly.
+// Junctions count double, as each is a chance to go wrong. A straight
+// corridor scores 0, most mazes between 1 and 2, and long winding ones
+// like the recursive backtracker's 2 and up.
+pub fn difficulty(
+    cells: usize,
+    turns: usize,
+    junctions: usize
+) -> f64 {
+    return (2 * junctions + turns) as f64 / (cells as f64).sqrt() / 4.0;
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::mazealgo::*;
+
+    // Each preset is met within a few seeds by the generators searched
+    // for it in game, on mazes of the default size and larger ones.
+    #[test]
+    fn presets_are_met_by_the_usual_generators() {
+
+        let generators: [&dyn MazeGenerator; 3] = [&Prim, &Kruskal, &RecursiveBacktracker];
+        for &side in [15, 30].iter() {
+            let cells = side * side;
+            for target in [MazeTarget::easy(cells), MazeTarget::medium(cells), MazeTarget::hard(cells)].iter() {
+                let mut maze = Maze2::empty(side, side);
+                let found = maze.generate_to_target(&generators, target, 1, 20);
+                assert!(found.is_some(), "No fit for {:?} at {}x{}", target, side, side);
+                assert!(target.accepts(&maze.stats()));
+            }
+        }
+
+    }
+
+}
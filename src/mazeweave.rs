@@ -0,0 +1,237 @@
+// Weave mazes.
+//
+// In a weave maze a corridor can pass over or under a perpendicular one.
+// The crossing cell is open on all four sides, but each passage runs
+// straight through it, see `Maze2::cross`.
+
+extern crate rand;
+
+use rand::Rng;
+use rand::RngCore;
+use crate::mazegen::*;
+
+// Generates a maze with another algorithm, then turns straight corridor
+// cells into crossings by tunnelling under or bridging over them. Every
+// tunnel closes a loop, which is broken again by walling up another
+// passage along it, so a perfect maze stays perfect.
+pub struct Woven<G: MazeGenerator> {
+    pub inner: G,
+    pub density: f64    // Share of straight corridor cells to cross (0.0 to 1.0)
+}
+
+// Axis of the straight corridor through a cell, if it is one and it can
+// be crossed.
+fn straight_axis(
+    maze: &Maze2,
+    x: usize,
+    y: usize
+) -> Option<u8> {
+
+    if maze.is_crossing(x, y) || maze.room_at(x, y).is_some() || maze.open_count(x, y) != 2 {
+        return None;
+    }
+    let dir = if maze.is_cell_open(x, y, Direction::PosX.as_u8()) { Direction::PosX } else { Direction::PosY };
+    if !maze.is_cell_open(x, y, dir.as_u8()) || !maze.is_cell_open(x, y, dir.opposite().as_u8()) {
+        return None;
+    }
+    return Some(dir.axis());
+
+}
+
+// Whether joining the given pairs of sets in turn joins a set to
+// itself, the sets given by their roots.
+fn closes_loop(
+    pairs: &[(usize, usize)]
+) -> bool {
+
+    let mut sets: Vec<Vec<usize>> = Vec::new();
+    for &(a, b) in pairs {
+        let set_a = sets.iter().position(|set| set.contains(&a));
+        let set_b = sets.iter().position(|set| set.contains(&b));
+        match (set_a, set_b) {
+            (Some(i), Some(j)) if i == j => return true,
+            (Some(i), Some(j)) => {
+                let other = sets.remove(i.max(j));
+                sets[i.min(j)].extend(other);
+            }
+            (Some(i), None) => sets[i].push(b),
+            (None, Some(j)) => sets[j].push(a),
+            (None, None) if a == b => return true,
+            (None, None) => sets.push(vec![a, b])
+        }
+    }
+    return false;
+
+}
+
+impl<G: MazeGenerator> MazeGenerator for Woven<G> {
+
+    fn generate(
+        &self,
+        maze: &mut Maze2,
+        rng: &mut dyn RngCore
+    ) {
+
+        self.inner.generate(maze, rng);
+
+        let density = self.density.max(0.0).min(1.0);
+        let size = maze.get_size();
+        let count = size.0 * size.1;
+        let mut cells: Vec<(usize, usize)> = Vec::new();
+        for x in 0..size.0 {
+            for y in 0..size.1 {
+                cells.push((x, y));
+            }
+        }
+        shuffle(rng, &mut cells);
+
+        // The maze as a graph of sets of cells joined by passages. Rooms
+        // count as one cell, as in `Rooms`. A crossing is two: the cell
+        // itself for the corridor it was made on, and the passage across
+        // it, along the axis kept in `across`.
+        let mut across: Vec<Option<u8>> = vec![None; count];
+        let node = |maze: &Maze2, across: &Vec<Option<u8>>, pos: (usize, usize), dir: u8| -> usize {
+            let i = maze.index(pos.0, pos.1);
+            return match maze.room_at(pos.0, pos.1) {
+                Some(room) => 2 * count + room,
+                None if across[i] == Some(dir >> 1) => count + i,
+                None => i
+            };
+        };
+        let mut parents: Vec<usize> = (0..2 * count + maze.borrow_rooms().len()).collect();
+        let root = |parents: &Vec<usize>, mut cell: usize| -> usize {
+            while parents[cell] != cell {
+                cell = parents[cell];
+            }
+            return cell;
+        };
+        let find = |parents: &mut Vec<usize>, mut cell: usize| -> usize {
+            while parents[cell] != cell {
+                parents[cell] = parents[parents[cell]];
+                cell = parents[cell];
+            }
+            return cell;
+        };
+
+        // Until the crossings are all made, `parents` only joins the
+        // passages through them, which cannot be walled up. Each crossing
+        // closes a loop, which must have another passage on it.
+        for (x, y) in cells {
+            // Earlier crossings may have changed the cell
+            let axis = match straight_axis(maze, x, y) {
+                Some(axis) => axis,
+                None => continue
+            };
+            if !rng.gen_bool(density) {
+                continue;
+            }
+
+            // The new passage joins the cells on either side
+            let along = if axis == 0 { Direction::PosX } else { Direction::PosY };
+            let across_dir = if axis == 0 { Direction::PosY } else { Direction::PosX };
+            let ends = (maze.next_cell((x, y), across_dir), maze.next_cell((x, y), across_dir.opposite()));
+            let (a, b) = match ends {
+                (Some(a), Some(b)) => (a, b),
+                _ => continue
+            };
+            if maze.is_crossing(a.0, a.1) || maze.is_crossing(b.0, b.1) {
+                continue;
+            }
+
+            // Passages the crossing adds to those that cannot be walled
+            // up: the corridor it is made on, unless it already was next
+            // to another crossing, and the new passage
+            let i = maze.index(x, y);
+            let mut joins: Vec<(usize, usize)> = Vec::new();
+            for &dir in [along, along.opposite()].iter() {
+                let next = maze.next_cell((x, y), dir).unwrap();
+                if !maze.is_crossing(next.0, next.1) {
+                    joins.push((node(maze, &across, next, dir.as_u8()), i));
+                }
+            }
+            joins.push((node(maze, &across, a, across_dir.as_u8()), count + i));
+            joins.push((node(maze, &across, b, across_dir.as_u8()), count + i));
+            let roots: Vec<(usize, usize)> = joins.iter()
+                .map(|&(p, q)| (root(&parents, p), root(&parents, q)))
+                .collect();
+            if closes_loop(&roots) {
+                continue;
+            }
+            for (p, q) in joins {
+                let (p, q) = (find(&mut parents, p), find(&mut parents, q));
+                parents[p] = q;
+            }
+            across[i] = Some(across_dir.axis());
+            maze.cross(x, y, rand_index(rng, 2) as u8);
+        }
+
+        // Keep a random spanning tree of the other passages, walling up
+        // one on every loop
+        let mut passages: Vec<(usize, usize, u8)> = Vec::new();
+        for x in 0..size.0 {
+            for y in 0..size.1 {
+                for &dir in [0, 2].iter() {
+                    if !maze.is_cell_open(x, y, dir) {
+                        continue;
+                    }
+                    if let Some((nx, ny)) = maze.neighbour(x, y, dir) {
+                        let inside_room = node(maze, &across, (x, y), dir) == node(maze, &across, (nx, ny), dir);
+                        if !inside_room && !maze.is_crossing(x, y) && !maze.is_crossing(nx, ny) {
+                            passages.push((x, y, dir));
+                        }
+                    }
+                }
+            }
+        }
+        shuffle(rng, &mut passages);
+        for (x, y, dir) in passages {
+            let next = maze.neighbour(x, y, dir).unwrap();
+            let p = find(&mut parents, node(maze, &across, (x, y), dir));
+            let q = find(&mut parents, node(maze, &across, next, dir));
+            if p == q {
+                maze.wall_cell(x, y, dir);
+            } else {
+                parents[p] = q;
+            }
+        }
+
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    // Crossings add a passage each and wall up another, so the maze
+    // stays a tree: every crossing counts as two cells, which are all
+    // reachable, with one passage less than there are cells.
+    #[test]
+    fn woven_mazes_stay_perfect() {
+
+        for &wrap in [false, true].iter() {
+            for seed in 0..20 {
+                let mut maze = Maze2::empty(12, 9);
+                maze.set_wrap(wrap, false);
+                maze.generate_using(&Woven { inner: Prim, density: 0.8 }, seed);
+
+                let size = maze.get_size();
+                let mut crossings: usize = 0;
+                let mut ends: u32 = 0;
+                for x in 0..size.0 {
+                    for y in 0..size.1 {
+                        crossings += maze.is_crossing(x, y) as usize;
+                        ends += maze.open_count(x, y);
+                    }
+                }
+                assert!(crossings > 0);
+                assert_eq!(ends as usize / 2, size.0 * size.1 + crossings - 1);
+                let reach = maze.reachable((0, 0), |_, _, _| false);
+                assert!(reach.iter().all(|&r| r));
+            }
+        }
+
+    }
+
+}
@@ -23,10 +23,13 @@ impl Triplef32 {
 pub struct Vertex {
     pub pos: Triplef32,
     pub col: Triplef32,
+    pub norm: Triplef32,    // Unit normal, for lighting
 }
 
 impl Vertex {
     
+    // renderer::mesh::Vertex::new()
+    // Vertex without a normal, see `Triangle::new`.
     pub fn new(
         pos: Triplef32,
         col: Triplef32,
@@ -34,7 +37,8 @@ impl Vertex {
         
         return Vertex {
             pos: pos,
-            col: col
+            col: col,
+            norm: Triplef32(0.0, 0.0, 0.0)
         };
 
     }
@@ -48,12 +52,35 @@ pub struct Triangle(pub Vertex, pub Vertex, pub Vertex);
 
 impl Triangle {
 
+    // renderer::mesh::Triangle::new()
+    // Triangle with the normal of its plane at all three vertices,
+    // pointing to the side from which they run counter-clockwise, the
+    // side left unculled. Degenerate triangles get a zero normal.
     pub fn new(a: Vertex, b: Vertex, c: Vertex) -> Triangle {
     
-        return Triangle(a, b, c);
+        let mut tri = Triangle(a, b, c);
+        let norm = tri.face_normal();
+        tri.0.norm = norm;
+        tri.1.norm = norm;
+        tri.2.norm = norm;
+        return tri;
     
     }
 
+    pub fn face_normal(&self) -> Triplef32 {
+
+        let (a, b, c) = (self.0.pos, self.1.pos, self.2.pos);
+        let u = (b.0 - a.0, b.1 - a.1, b.2 - a.2);
+        let v = (c.0 - a.0, c.1 - a.1, c.2 - a.2);
+        let n = (u.1 * v.2 - u.2 * v.1, u.2 * v.0 - u.0 * v.2, u.0 * v.1 - u.1 * v.0);
+        let len = (n.0 * n.0 + n.1 * n.1 + n.2 * n.2).sqrt();
+        if len == 0.0 {
+            return Triplef32(0.0, 0.0, 0.0);
+        }
+        return Triplef32(n.0 / len, n.1 / len, n.2 / len);
+
+    }
+
 }
 
 // renderer::mesh::MeshData
@@ -61,9 +88,11 @@ impl Triangle {
 pub struct MeshData {
     pub vbo_pos:    gl::types::GLuint,  // VBO for vertex positions
     pub vbo_col:    gl::types::GLuint,  // VBO for vertex colors
+    pub vbo_norm:   gl::types::GLuint,  // VBO for vertex normals
     pub vao:        gl::types::GLuint,  // VAO
     buf_pos:       Vec<Triplef32>,      // List of Triangles
     buf_col:       Vec<Triplef32>,      // List of Triangles
+    buf_norm:      Vec<Triplef32>,      // List of Triangles
     start_indices:  Vec<i32>,   // List of start triangle indices for meshes.
     buffer_lengths: Vec<isize>  // List of tiangle count for meshes
 }
@@ -76,15 +105,18 @@ impl MeshData {
         let mut msdat: MeshData = MeshData {
             vbo_pos: 1,
             vbo_col: 1,
+            vbo_norm: 1,
             vao: 1,
             buf_pos: Vec::new(),
             buf_col: Vec::new(),
+            buf_norm: Vec::new(),
             start_indices: Vec::new(),
             buffer_lengths: Vec::new()
         };
         unsafe {        
             gl::GenBuffers(1, &mut msdat.vbo_pos);
             gl::GenBuffers(1, &mut msdat.vbo_col);
+            gl::GenBuffers(1, &mut msdat.vbo_norm);
             gl::GenVertexArrays(1, &mut msdat.vao);
         }
         return msdat;
@@ -108,6 +140,14 @@ impl MeshData {
             self.buf_col.as_ptr() as *const gl::types::GLvoid,
             gl::STATIC_DRAW
         );
+        gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo_norm);
+        gl::BufferData(
+            gl::ARRAY_BUFFER,
+            (self.buf_norm.len() * std::mem::size_of::<Triplef32>()) 
+                as gl::types::GLsizeiptr,
+            self.buf_norm.as_ptr() as *const gl::types::GLvoid,
+            gl::STATIC_DRAW
+        );
         gl::BindBuffer(gl::ARRAY_BUFFER, 0);
     
     }
@@ -135,6 +175,16 @@ impl MeshData {
             std::mem::size_of::<Triplef32>() as gl::types::GLint,
             std::ptr::null()
         );
+        gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo_norm);
+        gl::EnableVertexAttribArray(2);
+        gl::VertexAttribPointer( 
+            2,
+            3,
+            gl::FLOAT,
+            gl::FALSE,
+            std::mem::size_of::<Triplef32>() as gl::types::GLint,
+            std::ptr::null()
+        );
         gl::BindBuffer(gl::ARRAY_BUFFER, 0);
         gl::BindVertexArray(0);
     
@@ -154,6 +204,9 @@ impl MeshData {
             self.buf_col.push(triangle.0.col);
             self.buf_col.push(triangle.1.col);
             self.buf_col.push(triangle.2.col);
+            self.buf_norm.push(triangle.0.norm);
+            self.buf_norm.push(triangle.1.norm);
+            self.buf_norm.push(triangle.2.norm);
         }
         self.start_indices.push(start_index);
         self.buffer_lengths.push(buf_len);
@@ -164,6 +217,22 @@ impl MeshData {
     
     }
 
+    // renderer::mesh::MeshData::clear()
+    // Drop all meshes, keeping the GL buffers for the next ones. Meshes
+    // created before are no longer valid.
+    pub fn clear(&mut self) {
+
+        self.buf_pos.clear();
+        self.buf_col.clear();
+        self.buf_norm.clear();
+        self.start_indices.clear();
+        self.buffer_lengths.clear();
+        unsafe {
+            self.buffer_data();
+        }
+
+    }
+
     pub fn draw_mesh(
         &self,
         buffer_index: usize
@@ -185,6 +254,196 @@ impl Drop for MeshData {
         unsafe{
             gl::DeleteBuffers(1, &self.vbo_pos);
             gl::DeleteBuffers(1, &self.vbo_col);
+            gl::DeleteBuffers(1, &self.vbo_norm);
+            gl::DeleteVertexArrays(1, &self.vao);
+        }
+    }
+
+}
+
+// renderer::mesh::IndexedMesh
+// Mesh in buffers of its own, with triangles sharing equal vertices
+// through an index buffer. Drawn in a single call.
+pub struct IndexedMesh {
+    vbo_pos:    gl::types::GLuint,  // VBO for vertex positions
+    vbo_col:    gl::types::GLuint,  // VBO for vertex colors
+    vbo_norm:   gl::types::GLuint,  // VBO for vertex normals
+    ebo:        gl::types::GLuint,  // Index buffer
+    vao:        gl::types::GLuint,  // VAO
+    buf_pos:    Vec<Triplef32>,     // List of distinct vertices
+    buf_col:    Vec<Triplef32>,
+    buf_norm:   Vec<Triplef32>,
+    indices:    Vec<u32>,           // Three per triangle
+    lookup:     std::collections::HashMap<[u32; 9], u32>   // Index of vertices by bits
+}
+
+impl IndexedMesh {
+
+    // renderer::mesh::IndexedMesh::new()
+    // Create an empty mesh and set up its VAO
+    pub fn new() -> IndexedMesh {
+
+        let mut mesh = IndexedMesh {
+            vbo_pos: 0,
+            vbo_col: 0,
+            vbo_norm: 0,
+            ebo: 0,
+            vao: 0,
+            buf_pos: Vec::new(),
+            buf_col: Vec::new(),
+            buf_norm: Vec::new(),
+            indices: Vec::new(),
+            lookup: std::collections::HashMap::new()
+        };
+        unsafe {
+            gl::GenBuffers(1, &mut mesh.vbo_pos);
+            gl::GenBuffers(1, &mut mesh.vbo_col);
+            gl::GenBuffers(1, &mut mesh.vbo_norm);
+            gl::GenBuffers(1, &mut mesh.ebo);
+            gl::GenVertexArrays(1, &mut mesh.vao);
+            mesh.vertex_attrib_pointer();
+        }
+        return mesh;
+
+    }
+
+    unsafe fn vertex_attrib_pointer(&self) {
+
+        gl::BindVertexArray(self.vao);
+        for (attr, vbo) in [self.vbo_pos, self.vbo_col, self.vbo_norm].iter().enumerate() {
+            gl::BindBuffer(gl::ARRAY_BUFFER, *vbo);
+            gl::EnableVertexAttribArray(attr as gl::types::GLuint);
+            gl::VertexAttribPointer(
+                attr as gl::types::GLuint,
+                3,
+                gl::FLOAT,
+                gl::FALSE,
+                std::mem::size_of::<Triplef32>() as gl::types::GLint,
+                std::ptr::null()
+            );
+        }
+        gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, self.ebo);
+        gl::BindVertexArray(0);
+        gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+
+    }
+
+    // renderer::mesh::IndexedMesh::clear()
+    // Drop all triangles. Call `buffer_data` once the new ones are added.
+    pub fn clear(&mut self) {
+
+        self.buf_pos.clear();
+        self.buf_col.clear();
+        self.buf_norm.clear();
+        self.indices.clear();
+        self.lookup.clear();
+
+    }
+
+    fn vertex_index(
+        &mut self,
+        vertex: Vertex
+    ) -> u32 {
+
+        let (pos, col, norm) = (vertex.pos, vertex.col, vertex.norm);
+        let key = [
+            pos.0.to_bits(), pos.1.to_bits(), pos.2.to_bits(),
+            col.0.to_bits(), col.1.to_bits(), col.2.to_bits(),
+            norm.0.to_bits(), norm.1.to_bits(), norm.2.to_bits()
+        ];
+        let next = self.buf_pos.len() as u32;
+        let index = *self.lookup.entry(key).or_insert(next);
+        if index == next {
+            self.buf_pos.push(pos);
+            self.buf_col.push(col);
+            self.buf_norm.push(norm);
+        }
+        return index;
+
+    }
+
+    pub fn add_triangles(
+        &mut self,
+        tris: &[Triangle]
+    ) {
+
+        for triangle in tris {
+            let (a, b, c) = (triangle.0, triangle.1, triangle.2);
+            for vertex in [a, b, c].iter() {
+                let index = self.vertex_index(*vertex);
+                self.indices.push(index);
+            }
+        }
+
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        return self.buf_pos.len();
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        return self.indices.len() / 3;
+    }
+
+    // renderer::mesh::IndexedMesh::buffer_data()
+    // Upload the triangles added since the last `clear`.
+    pub fn buffer_data(&self) {
+
+        unsafe {
+            let buffers = [
+                (self.vbo_pos, &self.buf_pos),
+                (self.vbo_col, &self.buf_col),
+                (self.vbo_norm, &self.buf_norm)
+            ];
+            for (vbo, buf) in buffers.iter() {
+                gl::BindBuffer(gl::ARRAY_BUFFER, *vbo);
+                gl::BufferData(
+                    gl::ARRAY_BUFFER,
+                    (buf.len() * std::mem::size_of::<Triplef32>())
+                        as gl::types::GLsizeiptr,
+                    buf.as_ptr() as *const gl::types::GLvoid,
+                    gl::STATIC_DRAW
+                );
+            }
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+            gl::BindVertexArray(self.vao);
+            gl::BufferData(
+                gl::ELEMENT_ARRAY_BUFFER,
+                (self.indices.len() * std::mem::size_of::<u32>())
+                    as gl::types::GLsizeiptr,
+                self.indices.as_ptr() as *const gl::types::GLvoid,
+                gl::STATIC_DRAW
+            );
+            gl::BindVertexArray(0);
+        }
+
+    }
+
+    pub fn render(&self) {
+
+        unsafe {
+            gl::BindVertexArray(self.vao);
+            gl::DrawElements(
+                gl::TRIANGLES,
+                self.indices.len() as gl::types::GLsizei,
+                gl::UNSIGNED_INT,
+                std::ptr::null()
+            );
+            gl::BindVertexArray(0);
+        }
+
+    }
+
+}
+
+impl Drop for IndexedMesh {
+
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteBuffers(1, &self.vbo_pos);
+            gl::DeleteBuffers(1, &self.vbo_col);
+            gl::DeleteBuffers(1, &self.vbo_norm);
+            gl::DeleteBuffers(1, &self.ebo);
             gl::DeleteVertexArrays(1, &self.vao);
         }
     }
@@ -195,8 +454,8 @@ impl Drop for MeshData {
 #[repr(C, packed)]
 pub struct Mesh {
     pub vao:        gl::types::GLuint, // VAO
-    start_index:    i32,    // Start index of triangle in buffer
-    buffer_length:  isize   // Triangle count
+    start_index:    i32,    // Index of the first vertex in the buffer
+    buffer_length:  isize   // Vertex count
 }
 
 impl Mesh {
@@ -221,8 +480,8 @@ impl Mesh {
             gl::BindVertexArray(self.vao);
             gl::DrawArrays(
                 gl::TRIANGLES,
-                3 * self.start_index as gl::types::GLint,
-                3 * self.buffer_length as gl::types::GLsizei
+                self.start_index as gl::types::GLint,
+                self.buffer_length as gl::types::GLsizei
             );
             gl::BindVertexArray(0);
         }